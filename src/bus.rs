@@ -0,0 +1,103 @@
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+use tokio::sync::{
+    broadcast::{self, error::RecvError, error::TryRecvError},
+    mpsc,
+};
+
+use crate::Message;
+
+/// The message bus connecting all parts of the application.
+///
+/// Messages are broadcast to all subscribers, which may miss messages
+/// if they fall behind. The recorder gets its own unbounded channel
+/// so that it never loses any data. Messages missed by subscribers
+/// are counted.
+#[derive(Clone, Debug)]
+pub struct Bus {
+    tx: broadcast::Sender<Message>,
+    recorder_tx: mpsc::UnboundedSender<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Bus {
+    /// Creates a new bus with room for `capacity` messages per
+    /// subscriber, and the receiving end of the recorder's channel.
+    pub fn new(capacity: usize) -> (Self, mpsc::UnboundedReceiver<Message>) {
+        let (tx, _) = broadcast::channel(capacity);
+        let (recorder_tx, recorder_rx) = mpsc::unbounded_channel();
+        (
+            Self {
+                tx,
+                recorder_tx,
+                dropped: Arc::new(AtomicU64::new(0)),
+            },
+            recorder_rx,
+        )
+    }
+
+    /// Publishes a message to the recorder and all subscribers.
+    pub fn send(&self, msg: Message) {
+        let _ = self.recorder_tx.send(msg.clone());
+        // This only fails if there are no subscribers right now.
+        let _ = self.tx.send(msg);
+    }
+
+    /// Publishes a message to all subscribers but not the recorder,
+    /// for the recorder's own messages.
+    pub fn send_to_subscribers(&self, msg: Message) {
+        let _ = self.tx.send(msg);
+    }
+
+    pub fn subscribe(&self) -> Subscriber {
+        Subscriber {
+            rx: self.tx.subscribe(),
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Number of messages subscribers have missed so far because
+    /// they fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Receiving end of the message bus.
+#[derive(Debug)]
+pub struct Subscriber {
+    rx: broadcast::Receiver<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscriber {
+    /// Waits for the next message, returns `None` once the bus has
+    /// been closed.
+    pub async fn recv(&mut self) -> Option<Message> {
+        loop {
+            match self.rx.recv().await {
+                Ok(msg) => return Some(msg),
+                Err(RecvError::Lagged(n)) => {
+                    self.dropped.fetch_add(n, Ordering::Relaxed);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Returns the next message if there is one.
+    pub fn try_recv(&mut self) -> Option<Message> {
+        loop {
+            match self.rx.try_recv() {
+                Ok(msg) => return Some(msg),
+                Err(TryRecvError::Lagged(n)) => {
+                    self.dropped.fetch_add(n, Ordering::Relaxed);
+                }
+                Err(_) => return None,
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::recorder::FileTemplate;
+
+/// Marks the start of a capture file.
+const MAGIC: &[u8; 8] = b"MCTLCAP1";
+
+/// Length of a chunk header, an 8 byte offset in microseconds since
+/// the start of the capture followed by a 4 byte chunk length, both
+/// little endian.
+const CHUNK_HEADER_LEN: usize = 12;
+
+/// A binary capture of the raw bytes read from a link, so that they
+/// can be fed through the decoder again later.
+///
+/// Every read is stored as a chunk with the time it arrived at,
+/// relative to when the capture was started.
+pub struct Capture {
+    file: File,
+    start: Instant,
+}
+
+impl Capture {
+    /// Creates a new capture file next to the exported sessions.
+    pub fn create(files: &FileTemplate) -> Result<(Self, String)> {
+        let path = files.path("bin")?;
+        let mut file =
+            File::create(&path).with_context(|| format!("Failed to open file: {}", path))?;
+        file.write_all(MAGIC)?;
+        Ok((
+            Self {
+                file,
+                start: Instant::now(),
+            },
+            path,
+        ))
+    }
+
+    /// Appends a chunk of bytes to the capture.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        let offset = self.start.elapsed().as_micros() as u64;
+        let mut chunk = Vec::with_capacity(CHUNK_HEADER_LEN + bytes.len());
+        chunk.extend_from_slice(&offset.to_le_bytes());
+        chunk.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        chunk.extend_from_slice(bytes);
+        self.file.write_all(&chunk)?;
+        Ok(())
+    }
+}
+
+/// Reads a capture file, returning every chunk with its offset from
+/// the start of the capture.
+pub fn read_capture(path: &Path) -> Result<Vec<(Duration, Vec<u8>)>> {
+    let data =
+        fs::read(path).with_context(|| format!("Failed to open file: {}", path.display()))?;
+    let Some(mut rest) = data.strip_prefix(MAGIC) else {
+        bail!("{} is not a capture file", path.display());
+    };
+
+    let mut chunks = vec![];
+    while !rest.is_empty() {
+        if rest.len() < CHUNK_HEADER_LEN {
+            bail!("Truncated chunk header in {}", path.display());
+        }
+        let offset = u64::from_le_bytes(rest[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(rest[8..12].try_into().unwrap()) as usize;
+        rest = &rest[CHUNK_HEADER_LEN..];
+        if rest.len() < len {
+            bail!("Truncated chunk in {}", path.display());
+        }
+        chunks.push((Duration::from_micros(offset), rest[..len].to_vec()));
+        rest = &rest[len..];
+    }
+    Ok(chunks)
+}
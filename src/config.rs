@@ -1,10 +1,13 @@
+use std::fmt;
 use std::fs;
 use std::path::PathBuf;
 
-use anyhow::{Context, Result};
-use serde::Deserialize;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Default, Deserialize)]
+use crate::log::{Log, Origin};
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Config {
     /// Number of seconds of data to display in graphs
     pub window_size: f32,
@@ -12,39 +15,162 @@ pub struct Config {
     /// Number of seconds after which data is considered stale
     pub data_timeout: f32,
 
+    /// Identifier of the vehicle, recorded with every session
+    #[serde(default)]
+    pub vehicle: String,
+
     /// Telemetry source
-    pub serial: Serial,
+    pub source: Source,
+
+    /// How sessions are recorded
+    #[serde(default)]
+    pub recording: Recording,
 
     /// Telemetry data to plot
     pub graphs: Vec<Graph>,
 
     /// Preset commands
     pub commands: Vec<Command>,
+
+    /// File to keep previously entered commands in
+    #[serde(default = "default_history_file")]
+    pub history_file: PathBuf,
+}
+
+fn default_history_file() -> PathBuf {
+    PathBuf::from(".mctl_history")
+}
+
+impl Config {
+    /// Switches the serial port, if that's the source.
+    pub fn select_port(&mut self, path: &str, baud: u32) {
+        if let Source::Serial(serial) = &mut self.source {
+            serial.path = path.to_string();
+            serial.baud = baud;
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Source {
+    /// Serial port, e.g. a USB tty or a radio modem
+    Serial(Serial),
+
+    /// TCP server, e.g. a software-in-the-loop simulator
+    Tcp(Tcp),
+
+    /// UDP socket, e.g. bridged to a radio modem
+    Udp(Udp),
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self::Serial(Serial::default())
+    }
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Serial {
     /// Data input serial port
     pub path: String,
 
     /// Serial port baud rate
     pub baud: u32,
+
+    /// Write every byte read to a binary capture file for debugging
+    #[serde(default)]
+    pub capture: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Tcp {
+    /// Address to connect to, e.g. `localhost:5760`
+    pub address: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Udp {
+    /// Local address to receive datagrams on, e.g. `0.0.0.0:14550`
+    pub bind: String,
+
+    /// Address to send commands to, defaults to the last peer we
+    /// received a datagram from
+    pub peer: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Recording {
+    /// File format to record sessions in
+    pub format: Format,
+
+    /// Directory to write session files to, created if needed
+    pub directory: PathBuf,
+
+    /// Name of session files without the extension, `{session}`,
+    /// `{vehicle}`, `{date}` and `{time}` are replaced
+    pub template: String,
+
+    /// Name for the sessions, e.g. the test campaign
+    pub session: String,
+
+    /// SQLite database in `directory` to record sessions into, if
+    /// that's the format
+    pub database: String,
+
+    /// Number of seconds of data to keep in memory for exporting
+    /// time ranges
+    pub history: f32,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self {
+            format: Format::default(),
+            directory: PathBuf::from("."),
+            template: "{session}-{date}T{time}".to_string(),
+            session: "mctl".to_string(),
+            database: "mctl.db".to_string(),
+            history: 600.,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// Wide CSV with local timestamps
+    #[default]
+    Csv,
+
+    /// Apache Parquet, only readable once the session is closed
+    Parquet,
+
+    /// Apache Arrow IPC stream, readable even after a crash
+    Arrow,
+
+    /// MCAP with JSON messages, for Foxglove Studio
+    Mcap,
+
+    /// SQLite database shared by all sessions
+    Sqlite,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Graph {
     pub name: String,
     pub plots: Vec<Plot>,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Plot {
     pub name: String,
     pub source_name: String,
     pub color: Color,
 }
 
-#[derive(Copy, Clone, Debug, Default, Deserialize)]
+#[derive(Copy, Clone, Debug, Default, Deserialize, Serialize)]
 pub enum Color {
     #[default]
     Red,
@@ -54,14 +180,255 @@ pub enum Color {
     Blue,
 }
 
-#[derive(Clone, Debug, Default, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Command {
     pub name: String,
+
+    /// Command to send, `{name}` is replaced with the value of the
+    /// parameter of that name
     pub command: String,
+
     pub color: Color,
+
+    /// Values to fill into the command
+    #[serde(default)]
+    pub params: Vec<Param>,
+
+    /// Key combination that sends the command, e.g. `Ctrl+Shift+R`
+    pub hotkey: Option<Hotkey>,
+}
+
+impl Command {
+    /// Fills in the parameter values, in the same order as `params`.
+    pub fn render(&self, values: &[String]) -> String {
+        self.params
+            .iter()
+            .zip(values)
+            .fold(self.command.clone(), |command, (param, value)| {
+                command.replace(&format!("{{{}}}", param.name), value)
+            })
+    }
+
+    /// Names of the `{name}` placeholders in the command.
+    fn placeholders(&self) -> Vec<&str> {
+        self.command
+            .split('{')
+            .skip(1)
+            .filter_map(|s| s.split_once('}').map(|(name, _)| name))
+            .collect()
+    }
+
+    fn validate(&self) -> Result<()> {
+        for placeholder in self.placeholders() {
+            if !self.params.iter().any(|p| p.name == placeholder) {
+                bail!("command {:?} has no parameter {placeholder:?}", self.name);
+            }
+        }
+        for param in &self.params {
+            param.validate().with_context(|| {
+                format!("invalid parameter {:?} of {:?}", param.name, self.name)
+            })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Param {
+    /// Name of the placeholder in the command
+    pub name: String,
+
+    #[serde(flatten)]
+    pub kind: ParamKind,
+}
+
+impl Param {
+    fn validate(&self) -> Result<()> {
+        match &self.kind {
+            ParamKind::Float { min, max, step, .. } if min >= max || *step <= 0. => {
+                bail!("min must be below max and step positive")
+            }
+            ParamKind::Float {
+                min,
+                max,
+                default: Some(default),
+                ..
+            } if !(min..=max).contains(&default) => bail!("default must be within min and max"),
+            ParamKind::Int { min, max, .. } if min >= max => bail!("min must be below max"),
+            ParamKind::Int {
+                min,
+                max,
+                default: Some(default),
+            } if !(min..=max).contains(&default) => bail!("default must be within min and max"),
+            ParamKind::Enum { options, .. } if options.is_empty() => bail!("no options"),
+            ParamKind::Enum {
+                options,
+                default: Some(default),
+            } if !options.contains(default) => bail!("default is not one of the options"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ParamKind {
+    /// Number picked with a slider
+    Float {
+        min: f32,
+        max: f32,
+        step: f32,
+        default: Option<f32>,
+    },
+
+    /// Whole number picked with a spinner
+    Int {
+        min: i32,
+        max: i32,
+        default: Option<i32>,
+    },
+
+    /// One of a few options picked from a dropdown
+    Enum {
+        options: Vec<String>,
+        default: Option<String>,
+    },
+
+    /// Checkbox, sent as `on` or `off`
+    Bool {
+        #[serde(default)]
+        default: bool,
+        #[serde(default = "default_on")]
+        on: String,
+        #[serde(default = "default_off")]
+        off: String,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Hotkey {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+
+    /// Name of the key, a letter, a digit, `F1` to `F12`, `Space`,
+    /// `Minus` or `Plus`
+    pub key: String,
+}
+
+impl TryFrom<String> for Hotkey {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        let mut parts = s.split('+').map(str::trim).collect::<Vec<_>>();
+        let key = match parts.pop().unwrap() {
+            k if k.len() == 1 => k.to_ascii_uppercase(),
+            k => k.to_string(),
+        };
+        let valid_key = match key.as_str() {
+            "Space" | "Minus" | "Plus" => true,
+            k if k.len() == 1 => k
+                .chars()
+                .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit()),
+            k => matches!(k.strip_prefix('F').map(str::parse), Some(Ok(1..=12))),
+        };
+        if !valid_key {
+            bail!("unknown key {key:?} in hotkey {s:?}");
+        }
+        let mut hotkey = Self {
+            ctrl: false,
+            alt: false,
+            shift: false,
+            key,
+        };
+        for modifier in parts {
+            let held = match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => &mut hotkey.ctrl,
+                "alt" | "option" => &mut hotkey.alt,
+                "shift" => &mut hotkey.shift,
+                _ => bail!("unknown modifier {modifier:?} in hotkey {s:?}"),
+            };
+            if *held {
+                bail!("modifier {modifier:?} repeated in hotkey {s:?}");
+            }
+            *held = true;
+        }
+        Ok(hotkey)
+    }
+}
+
+impl From<Hotkey> for String {
+    fn from(hotkey: Hotkey) -> Self {
+        hotkey.to_string()
+    }
+}
+
+impl fmt::Display for Hotkey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (held, name) in [
+            (self.ctrl, "Ctrl"),
+            (self.alt, "Alt"),
+            (self.shift, "Shift"),
+        ] {
+            if held {
+                write!(f, "{name}+")?;
+            }
+        }
+        write!(f, "{}", self.key)
+    }
+}
+
+fn default_on() -> String {
+    "1".to_string()
+}
+
+fn default_off() -> String {
+    "0".to_string()
 }
 
 pub fn load_config(path: &PathBuf) -> Result<Config> {
-    toml::from_str(&fs::read_to_string(path).context("unable to read config file")?)
-        .context("unable to parse config file")
+    let mut table: toml::Table =
+        toml::from_str(&fs::read_to_string(path).context("unable to read config file")?)
+            .context("unable to parse config file")?;
+    migrate_serial(&mut table);
+    let config: Config = toml::Value::Table(table)
+        .try_into()
+        .context("unable to parse config file")?;
+    for (i, command) in config.commands.iter().enumerate() {
+        command.validate().context("invalid config file")?;
+        let Some(hotkey) = &command.hotkey else {
+            continue;
+        };
+        if let Some(other) = config.commands[..i]
+            .iter()
+            .find(|c| c.hotkey.as_ref() == Some(hotkey))
+        {
+            bail!(
+                "invalid config file: hotkey {hotkey} is bound to both {:?} and {:?}",
+                other.name,
+                command.name
+            );
+        }
+    }
+    Ok(config)
+}
+
+/// Turns a `[serial]` section from before there were other sources
+/// into the equivalent `[source]`.
+fn migrate_serial(table: &mut toml::Table) {
+    if table.contains_key("source") {
+        return;
+    }
+    if let Some(toml::Value::Table(mut serial)) = table.remove("serial") {
+        println!(
+            "{}",
+            Log::warning(
+                Origin::System,
+                "[serial] in the config file is deprecated, use [source] with type = \"serial\"",
+            )
+        );
+        serial.insert("type".to_string(), "serial".into());
+        table.insert("source".to_string(), toml::Value::Table(serial));
+    }
 }
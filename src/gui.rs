@@ -2,16 +2,36 @@ use anyhow::Result;
 use eframe::egui;
 use egui::widgets::plot::LinkedCursorsGroup;
 use time::{Duration, OffsetDateTime};
-use tokio::sync::broadcast::{Receiver, Sender};
 
 mod color;
+mod commands;
+mod console;
+mod export;
 mod graph;
+mod input;
+mod link;
 
-use crate::{config::Config, Command, Message};
+use crate::{
+    bus::{Bus, Subscriber},
+    config::{self, Config},
+    log::{Log, Origin},
+    Command, Message,
+};
 use color::*;
+use commands::CommandPanel;
+use console::Console;
+use export::RangeExport;
 use graph::Graph;
+use input::CommandInput;
+use link::{LinkMonitor, PortPicker};
 
-pub fn run(cfg: Config, rx: Receiver<Message>, tx: Sender<Message>) -> Result<()> {
+/// Shown on the session controls when nothing is being recorded.
+const RECORDING_OFF: &str = "Replays are only recorded with --record";
+
+/// Runs the window until it is closed. `replay` hides the controls
+/// for the live link, and without `record` the session controls are
+/// disabled.
+pub fn run(cfg: Config, replay: bool, record: bool, rx: Subscriber, tx: Bus) -> Result<()> {
     let native_options = eframe::NativeOptions {
         initial_window_size: Some(egui::vec2(1024., 768.)),
         // maximized: true,
@@ -20,7 +40,7 @@ pub fn run(cfg: Config, rx: Receiver<Message>, tx: Sender<Message>) -> Result<()
     eframe::run_native(
         "Mission Control",
         native_options,
-        Box::new(|cc| Box::new(App::new(cc, cfg, rx, tx))),
+        Box::new(move |cc| Box::new(App::new(cc, cfg, replay, record, rx, tx))),
     )
     .map_err(|e| anyhow::anyhow!("Failed to run gui: {}", e))?;
     Ok(())
@@ -31,20 +51,48 @@ struct App {
     last_data: OffsetDateTime,
     config: Config,
     graphs: Vec<Graph>,
-    input_text: String,
-    rx: Receiver<Message>,
-    tx: Sender<Message>,
+    link: LinkMonitor,
+    console: Console,
+    commands: CommandPanel,
+    port_picker: Option<PortPicker>,
+    show_decoder: bool,
+    range_export: RangeExport,
+    show_range_export: bool,
+    input: CommandInput,
+    /// Whether a recorder is running.
+    record: bool,
+    /// Operator notes for the current session.
+    notes: String,
+    rx: Subscriber,
+    tx: Bus,
 }
 
 impl App {
     fn new(
         _cc: &eframe::CreationContext<'_>,
         cfg: Config,
-        rx: Receiver<Message>,
-        tx: Sender<Message>,
+        replay: bool,
+        record: bool,
+        rx: Subscriber,
+        tx: Bus,
     ) -> Self {
         let cursor_group = LinkedCursorsGroup::new(true, false);
         let now = OffsetDateTime::now_local().expect("failed to get local time");
+        let mut console = Console::default();
+        let commands = cfg
+            .commands
+            .iter()
+            // Only complete templates up to their first parameter
+            .map(|c| c.command.split('{').next().unwrap().to_string())
+            .collect::<Vec<_>>();
+        let input =
+            CommandInput::load(cfg.history_file.clone(), commands.clone()).unwrap_or_else(|e| {
+                console.push(Log::warning(
+                    Origin::System,
+                    format!("Failed to load command history: {e:#}"),
+                ));
+                CommandInput::new(cfg.history_file.clone(), commands)
+            });
         Self {
             start_time: now,
             last_data: now,
@@ -64,7 +112,21 @@ impl App {
                     )
                 })
                 .collect(),
-            input_text: String::new(),
+            link: LinkMonitor::default(),
+            console,
+            commands: CommandPanel::new(&cfg.commands),
+            port_picker: match &cfg.source {
+                config::Source::Serial(serial) if !replay => {
+                    Some(PortPicker::new(&serial.path, serial.baud))
+                }
+                _ => None,
+            },
+            show_decoder: false,
+            range_export: RangeExport::new(cfg.recording.format),
+            show_range_export: false,
+            input,
+            record,
+            notes: String::new(),
             rx,
             tx,
         }
@@ -72,15 +134,30 @@ impl App {
 }
 
 impl eframe::App for App {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+    fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
         let now = OffsetDateTime::now_local().unwrap();
+        let mut quit = false;
 
-        while let Ok(msg) = self.rx.try_recv() {
-            if let Message::Telemetry(frame) = msg {
-                for graph in self.graphs.iter_mut() {
-                    graph.add_data(&frame);
+        while let Some(msg) = self.rx.try_recv() {
+            match msg {
+                Message::Telemetry(frame) => {
+                    for graph in self.graphs.iter_mut() {
+                        graph.add_data(&frame);
+                    }
+                    self.last_data = now;
+                }
+                Message::Log(log) => {
+                    self.input.learn(&log);
+                    self.console.push(log);
+                }
+                Message::Link(status) => {
+                    self.console.push(status.to_log());
+                    self.link.set_status(status);
                 }
-                self.last_data = now;
+                Message::CommandSent(sent) => self.console.push(sent.to_log()),
+                Message::LinkStats(stats) => self.link.add_stats(stats, now),
+                Message::Command(Command::Exit) => quit = true,
+                _ => {}
             }
         }
 
@@ -119,21 +196,39 @@ impl eframe::App for App {
                     ui.separator();
                     ui.label(format!("LDT: {:.2}", data_age));
                 }
+                ui.separator();
+                self.link.draw(ui);
+                let dropped = self.tx.dropped();
+                if dropped > 0 {
+                    ui.separator();
+                    ui.label(
+                        egui::RichText::new(format!("DROPPED: {dropped}"))
+                            .background_color(YELLOW)
+                            .color(egui::Color32::BLACK)
+                            .strong(),
+                    )
+                    .on_hover_text("Messages missed because the message bus was saturated");
+                }
             });
         });
 
         egui::containers::TopBottomPanel::bottom("Input").show(ctx, |ui| {
-            let response = ui.add(
-                egui::widgets::TextEdit::singleline(&mut self.input_text)
-                    .desired_width(ui.available_width()),
-            );
-            if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
-                send_command(Command::SendCommand(self.input_text.clone()), &self.tx);
-                self.input_text.clear();
-                response.request_focus();
+            if let Some(command) = self.input.draw(ui) {
+                if let Err(e) = self.input.remember(&command) {
+                    self.console.push(Log::warning(
+                        Origin::System,
+                        format!("Failed to save command history: {e:#}"),
+                    ));
+                }
+                send_command(Command::SendCommand(command), &self.tx);
             }
         });
 
+        egui::containers::TopBottomPanel::bottom("Console")
+            .resizable(true)
+            .default_height(160.)
+            .show(ctx, |ui| self.console.draw(ui));
+
         egui::containers::SidePanel::left("Commands")
             .resizable(false)
             .show(ctx, |ui| {
@@ -144,19 +239,8 @@ impl eframe::App for App {
                         ui.heading("Commands");
                         ui.set_width(120.);
                         ui.vertical(|ui| {
-                            for command in &self.config.commands {
-                                let button = egui::Button::new(
-                                    egui::RichText::new(&command.name)
-                                        .color(egui::Color32::BLACK)
-                                        .strong(),
-                                )
-                                .fill(egui_color(command.color));
-                                if ui.add(button).clicked() {
-                                    send_command(
-                                        Command::SendCommand(command.command.clone()),
-                                        &self.tx,
-                                    );
-                                };
+                            if let Some(command) = self.commands.draw(ui) {
+                                send_command(Command::SendCommand(command), &self.tx);
                             }
                         });
                     });
@@ -171,15 +255,64 @@ impl eframe::App for App {
                         ui.vertical(|ui| {
                             ui.set_width(140.);
                             ui.heading("System");
-                            if ui.button("Save to disk").clicked() {
+                            if ui
+                                .add_enabled(self.record, egui::Button::new("Save to disk"))
+                                .on_hover_text("Close the session file and start a new one")
+                                .on_disabled_hover_text(RECORDING_OFF)
+                                .clicked()
+                            {
                                 send_command(Command::Export, &self.tx);
+                                self.notes.clear();
+                            };
+                            if ui
+                                .add_enabled(self.record, egui::Button::new("Export range"))
+                                .on_hover_text("Export part of the recent data to a new file")
+                                .on_disabled_hover_text(RECORDING_OFF)
+                                .clicked()
+                            {
+                                self.show_range_export = !self.show_range_export;
+                            };
+
+                            ui.add_space(20.);
+                            ui.label("Session notes");
+                            if ui
+                                .add_enabled(
+                                    self.record,
+                                    egui::TextEdit::multiline(&mut self.notes)
+                                        .desired_rows(4)
+                                        .hint_text("Saved with the session"),
+                                )
+                                .on_disabled_hover_text(RECORDING_OFF)
+                                .changed()
+                            {
+                                send_command(Command::SetNotes(self.notes.clone()), &self.tx);
+                            }
+
+                            if let Some(port_picker) = &mut self.port_picker {
+                                ui.add_space(20.);
+                                ui.label("Serial port");
+                                if let Some((path, baud)) = port_picker.draw(ui) {
+                                    send_command(Command::SelectPort { path, baud }, &self.tx);
+                                }
+                            }
+
+                            ui.add_space(20.);
+
+                            if ui.button("Decoder health").clicked() {
+                                self.show_decoder = !self.show_decoder;
                             };
 
                             ui.add_space(20.);
 
-                            if ui.button("Reset").clicked() {
+                            let reset = if self.record {
+                                "Clear the graphs and discard the session file"
+                            } else {
+                                "Clear the graphs"
+                            };
+                            if ui.button("Reset").on_hover_text(reset).clicked() {
+                                send_command(Command::Reset, &self.tx);
+                                self.notes.clear();
                                 for graph in &mut self.graphs {
-                                    send_command(Command::Reset, &self.tx);
                                     graph.reset();
                                 }
                             };
@@ -194,12 +327,24 @@ impl eframe::App for App {
                                 )
                                 .clicked()
                             {
-                                std::process::exit(0);
+                                quit = true;
                             };
                         });
                     });
             });
 
+        egui::Window::new("Decoder health")
+            .open(&mut self.show_decoder)
+            .show(ctx, |ui| self.link.draw_decoder(ui));
+
+        egui::Window::new("Export range")
+            .open(&mut self.show_range_export)
+            .show(ctx, |ui| {
+                if let Some(cmd) = self.range_export.draw(ui, now) {
+                    send_command(cmd, &self.tx);
+                }
+            });
+
         egui::containers::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Telemetry");
             egui::ScrollArea::new([true, true])
@@ -217,12 +362,16 @@ impl eframe::App for App {
                 })
         });
 
+        if quit {
+            // Shutting down the rest of the application is handled
+            // once the window has closed.
+            frame.close();
+        }
+
         ctx.request_repaint_after(std::time::Duration::from_millis(20));
     }
 }
 
-fn send_command(cmd: Command, bus: &Sender<Message>) {
-    if let Err(e) = bus.send(Message::Command(cmd)) {
-        eprintln!("[SYSTEM] Error sending command: {}", e);
-    }
+fn send_command(cmd: Command, bus: &Bus) {
+    bus.send(Message::Command(cmd));
 }
@@ -0,0 +1,235 @@
+use eframe::egui;
+
+use crate::{
+    config::{self, Hotkey, ParamKind},
+    gui::color::egui_color,
+};
+
+/// Keys that hotkeys can use.
+const KEYS: [egui::Key; 51] = {
+    use egui::Key::*;
+    [
+        A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Num0, Num1,
+        Num2, Num3, Num4, Num5, Num6, Num7, Num8, Num9, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10,
+        F11, F12, Space, Minus, PlusEquals,
+    ]
+};
+
+/// Current value of a command parameter.
+enum Value {
+    Float(f32),
+    Int(i32),
+    /// Index into the options.
+    Enum(usize),
+    Bool(bool),
+}
+
+impl Value {
+    fn new(kind: &ParamKind) -> Self {
+        match kind {
+            ParamKind::Float { min, default, .. } => Self::Float(default.unwrap_or(*min)),
+            ParamKind::Int { min, default, .. } => Self::Int(default.unwrap_or(*min)),
+            ParamKind::Enum { options, default } => Self::Enum(
+                default
+                    .as_ref()
+                    .and_then(|d| options.iter().position(|o| o == d))
+                    .unwrap_or(0),
+            ),
+            ParamKind::Bool { default, .. } => Self::Bool(*default),
+        }
+    }
+
+    fn to_string(&self, kind: &ParamKind) -> String {
+        match (self, kind) {
+            (
+                Self::Float(value),
+                ParamKind::Float {
+                    min,
+                    max,
+                    step,
+                    default,
+                },
+            ) => {
+                // The slider snaps to multiples of the step, unless
+                // it's at the ends or hasn't been moved yet.
+                let decimals = [*min, *max, *step, default.unwrap_or(*min)]
+                    .into_iter()
+                    .map(decimals)
+                    .max()
+                    .unwrap();
+                format!("{value:.decimals$}")
+            }
+            (Self::Int(value), _) => value.to_string(),
+            (Self::Enum(index), ParamKind::Enum { options, .. }) => options[*index].clone(),
+            (Self::Bool(value), ParamKind::Bool { on, off, .. }) => {
+                if *value { on } else { off }.clone()
+            }
+            _ => unreachable!("parameter value does not match its kind"),
+        }
+    }
+
+    fn draw(&mut self, ui: &mut egui::Ui, param: &config::Param, id: egui::Id) {
+        if !matches!(self, Self::Bool(_)) {
+            ui.label(&param.name);
+        }
+        match (self, &param.kind) {
+            (Self::Float(value), ParamKind::Float { min, max, step, .. }) => {
+                ui.add(egui::Slider::new(value, *min..=*max).step_by(*step as f64));
+            }
+            (Self::Int(value), ParamKind::Int { min, max, .. }) => {
+                ui.add(egui::DragValue::new(value).clamp_range(*min..=*max));
+            }
+            (Self::Enum(index), ParamKind::Enum { options, .. }) => {
+                egui::ComboBox::from_id_source(id)
+                    .selected_text(&options[*index])
+                    .show_ui(ui, |ui| {
+                        for (i, option) in options.iter().enumerate() {
+                            ui.selectable_value(index, i, option);
+                        }
+                    });
+            }
+            (Self::Bool(value), ParamKind::Bool { .. }) => {
+                ui.checkbox(value, &param.name);
+            }
+            _ => unreachable!("parameter value does not match its kind"),
+        }
+    }
+}
+
+/// Number of decimals in the shortest form of a number.
+fn decimals(number: f32) -> usize {
+    number
+        .to_string()
+        .split_once('.')
+        .map_or(0, |(_, fraction)| fraction.len())
+}
+
+/// A preset command along with the values picked for its parameters.
+struct Preset {
+    command: config::Command,
+    values: Vec<Value>,
+    shortcut: Option<egui::KeyboardShortcut>,
+}
+
+impl Preset {
+    fn new(command: &config::Command) -> Self {
+        Self {
+            values: command.params.iter().map(|p| Value::new(&p.kind)).collect(),
+            shortcut: command.hotkey.as_ref().map(shortcut),
+            command: command.clone(),
+        }
+    }
+
+    /// The command to send with the current parameter values.
+    fn render(&self) -> String {
+        let values = self
+            .values
+            .iter()
+            .zip(&self.command.params)
+            .map(|(v, p)| v.to_string(&p.kind))
+            .collect::<Vec<_>>();
+        self.command.render(&values)
+    }
+}
+
+/// Buttons for the preset commands, with controls for their
+/// parameters.
+pub struct CommandPanel {
+    presets: Vec<Preset>,
+}
+
+impl CommandPanel {
+    pub fn new(commands: &[config::Command]) -> Self {
+        Self {
+            presets: commands.iter().map(Preset::new).collect(),
+        }
+    }
+
+    /// Draws the commands, returning the one to send if a button was
+    /// clicked or its hotkey pressed.
+    pub fn draw(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        let mut clicked = None;
+        // Don't steal keys from whatever is being typed
+        if !ui.ctx().wants_keyboard_input() {
+            for preset in &self.presets {
+                if let Some(shortcut) = &preset.shortcut {
+                    if ui.input_mut(|i| consume_press(i, shortcut)) {
+                        clicked = Some(preset.render());
+                    }
+                }
+            }
+        }
+        for (i, preset) in self.presets.iter_mut().enumerate() {
+            let mut button = egui::Button::new(
+                egui::RichText::new(&preset.command.name)
+                    .color(egui::Color32::BLACK)
+                    .strong(),
+            )
+            .fill(egui_color(preset.command.color));
+            if let Some(hotkey) = &preset.command.hotkey {
+                button = button.shortcut_text(
+                    egui::RichText::new(hotkey.to_string()).color(egui::Color32::BLACK),
+                );
+            }
+            if preset.values.is_empty() {
+                if ui.add(button).clicked() {
+                    clicked = Some(preset.render());
+                }
+                continue;
+            }
+            ui.group(|ui| {
+                for (j, (value, param)) in preset
+                    .values
+                    .iter_mut()
+                    .zip(&preset.command.params)
+                    .enumerate()
+                {
+                    value.draw(ui, param, egui::Id::new(("Command param", i, j)));
+                }
+                let response = ui.add(button);
+                let command = preset.render();
+                if response.on_hover_text(&command).clicked() {
+                    clicked = Some(command);
+                }
+            });
+        }
+        clicked
+    }
+}
+
+/// The egui equivalent of a hotkey from the config, which has already
+/// made sure the key is one of [`KEYS`].
+fn shortcut(hotkey: &Hotkey) -> egui::KeyboardShortcut {
+    let key = KEYS
+        .into_iter()
+        .find(|k| k.name() == hotkey.key)
+        .expect("unknown hotkey");
+    let modifiers = egui::Modifiers {
+        alt: hotkey.alt,
+        ctrl: hotkey.ctrl,
+        shift: hotkey.shift,
+        ..Default::default()
+    };
+    egui::KeyboardShortcut::new(modifiers, key)
+}
+
+/// Consumes the shortcut's key events, returning whether it was
+/// pressed. Unlike [`egui::InputState::consume_shortcut`] this
+/// ignores key repeats, so that holding the keys sends the command
+/// only once.
+fn consume_press(input: &mut egui::InputState, shortcut: &egui::KeyboardShortcut) -> bool {
+    let mut pressed = false;
+    input.events.retain(|event| match event {
+        egui::Event::Key {
+            key,
+            pressed: true,
+            repeat,
+            modifiers,
+        } if *key == shortcut.key && modifiers.matches(shortcut.modifiers) => {
+            pressed |= !repeat;
+            false
+        }
+        _ => true,
+    });
+    pressed
+}
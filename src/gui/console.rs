@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+
+use eframe::egui;
+use time::{format_description::FormatItem, macros::format_description};
+
+use crate::{
+    gui::color::{RED, YELLOW},
+    log::{Log, Origin, Severity},
+};
+
+/// Number of lines to keep before dropping the oldest ones.
+const MAX_LINES: usize = 5_000;
+
+const TIMESTAMP_FORMAT: &[FormatItem<'_>] =
+    format_description!("[hour]:[minute]:[second].[subsecond digits:3]");
+
+/// A log, formatted once when it arrives.
+struct Line {
+    text: String,
+    /// The message in lowercase, for searching.
+    message: String,
+    severity: Severity,
+    origin: Origin,
+}
+
+/// Which lines to show.
+#[derive(Clone, PartialEq)]
+struct Filter {
+    /// Lowercase text the message has to contain.
+    search: String,
+    /// Least severe logs to show.
+    min_severity: Severity,
+    /// Origins to show logs from.
+    origins: Vec<Origin>,
+}
+
+impl Filter {
+    fn shows(&self, line: &Line) -> bool {
+        line.severity >= self.min_severity
+            && self.origins.contains(&line.origin)
+            && (self.search.is_empty() || line.message.contains(&self.search))
+    }
+}
+
+/// Shows log messages from the vehicle and the rest of the
+/// application.
+pub struct Console {
+    lines: VecDeque<Line>,
+    /// Number of lines dropped so far, to turn line numbers into
+    /// indices into `lines`.
+    dropped: usize,
+    /// Numbers of the lines passing the filter.
+    shown: VecDeque<usize>,
+    /// The filter `shown` was built with.
+    filter: Filter,
+    search: String,
+    auto_scroll: bool,
+    /// Least severe logs to show.
+    min_severity: Severity,
+    /// Origins to show logs from.
+    origins: Vec<Origin>,
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self {
+            lines: VecDeque::new(),
+            dropped: 0,
+            shown: VecDeque::new(),
+            filter: Filter {
+                search: String::new(),
+                min_severity: Severity::Debug,
+                origins: Origin::ALL.to_vec(),
+            },
+            search: String::new(),
+            auto_scroll: true,
+            min_severity: Severity::Debug,
+            origins: Origin::ALL.to_vec(),
+        }
+    }
+}
+
+impl Console {
+    pub fn push(&mut self, log: Log) {
+        let line = Line {
+            text: format!("{} {log}", log.timestamp.format(TIMESTAMP_FORMAT).unwrap()),
+            message: log.message.to_lowercase(),
+            severity: log.severity,
+            origin: log.origin,
+        };
+        if self.filter.shows(&line) {
+            self.shown.push_back(self.dropped + self.lines.len());
+        }
+        self.lines.push_back(line);
+        while self.lines.len() > MAX_LINES {
+            self.lines.pop_front();
+            if self.shown.front() == Some(&self.dropped) {
+                self.shown.pop_front();
+            }
+            self.dropped += 1;
+        }
+    }
+
+    /// Picks the lines to show again if the filter has changed.
+    fn refilter(&mut self) {
+        let filter = Filter {
+            search: self.search.to_lowercase(),
+            min_severity: self.min_severity,
+            origins: self.origins.clone(),
+        };
+        if filter == self.filter {
+            return;
+        }
+        self.shown = self
+            .lines
+            .iter()
+            .enumerate()
+            .filter(|(_, l)| filter.shows(l))
+            .map(|(i, _)| self.dropped + i)
+            .collect();
+        self.filter = filter;
+    }
+
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
+        let mut clear = false;
+        ui.horizontal(|ui| {
+            ui.heading("Console");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.search)
+                    .hint_text("Search")
+                    .desired_width(200.),
+            );
+            egui::ComboBox::from_id_source("Console severity")
+                .selected_text(format!("{}+", self.min_severity))
+                .show_ui(ui, |ui| {
+                    for severity in Severity::ALL {
+                        ui.selectable_value(&mut self.min_severity, severity, severity.to_string());
+                    }
+                });
+            for origin in Origin::ALL {
+                let mut shown = self.origins.contains(&origin);
+                if ui.checkbox(&mut shown, origin.to_string()).changed() {
+                    if shown {
+                        self.origins.push(origin);
+                    } else {
+                        self.origins.retain(|o| *o != origin);
+                    }
+                }
+            }
+            ui.checkbox(&mut self.auto_scroll, "Auto-scroll");
+            if ui
+                .button("Copy")
+                .on_hover_text("Copy the lines shown to the clipboard")
+                .clicked()
+            {
+                let text = self
+                    .shown
+                    .iter()
+                    .map(|n| self.lines[n - self.dropped].text.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                ui.output_mut(|o| o.copied_text = text);
+            }
+            clear = ui.button("Clear").clicked();
+        });
+
+        self.refilter();
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .stick_to_bottom(self.auto_scroll)
+            .show_rows(ui, row_height, self.shown.len(), |ui, rows| {
+                for n in self.shown.range(rows) {
+                    let line = &self.lines[n - self.dropped];
+                    let text = egui::RichText::new(&line.text).monospace();
+                    ui.label(match line.severity {
+                        Severity::Error => text.color(RED),
+                        Severity::Warning => text.color(YELLOW),
+                        _ => text,
+                    });
+                }
+            });
+        if clear {
+            self.dropped += self.lines.len();
+            self.lines.clear();
+            self.shown.clear();
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use eframe::egui;
+use time::{
+    format_description::FormatItem, macros::format_description, Duration, OffsetDateTime, Time,
+};
+
+use crate::{config::Format, gui::color::RED, Command};
+
+/// Format of the start and end times entered by the operator.
+const TIME_FORMAT: &[FormatItem<'_>] = format_description!("[hour]:[minute]:[second]");
+
+/// Formats ranges can be exported in. Not SQLite, as that would add
+/// the range to the database as another session.
+const FORMATS: [(Format, &str); 4] = [
+    (Format::Csv, "CSV"),
+    (Format::Parquet, "Parquet"),
+    (Format::Arrow, "Arrow IPC"),
+    (Format::Mcap, "MCAP"),
+];
+
+/// Lets the operator export just the data between two times of day,
+/// without touching the current session.
+pub struct RangeExport {
+    start: String,
+    end: String,
+    format: Format,
+    error: Option<String>,
+}
+
+impl RangeExport {
+    pub fn new(format: Format) -> Self {
+        Self {
+            start: String::new(),
+            end: String::new(),
+            format: match format {
+                Format::Sqlite => Format::Csv,
+                format => format,
+            },
+            error: None,
+        }
+    }
+
+    /// Selects the last `seconds` seconds.
+    fn select_last(&mut self, seconds: i64, now: OffsetDateTime) {
+        self.start = (now - Duration::seconds(seconds))
+            .format(TIME_FORMAT)
+            .unwrap();
+        self.end = now.format(TIME_FORMAT).unwrap();
+        self.error = None;
+    }
+
+    /// Draws the export form, returning the command to send if the
+    /// operator asked to export.
+    pub fn draw(&mut self, ui: &mut egui::Ui, now: OffsetDateTime) -> Option<Command> {
+        if self.start.is_empty() && self.end.is_empty() {
+            self.select_last(30, now);
+        }
+
+        egui::Grid::new("Export range").show(ui, |ui| {
+            ui.label("Start");
+            ui.text_edit_singleline(&mut self.start);
+            ui.end_row();
+            ui.label("End");
+            ui.text_edit_singleline(&mut self.end);
+            ui.end_row();
+            ui.label("Format");
+            egui::ComboBox::from_id_source("Export format")
+                .selected_text(FORMATS.iter().find(|(f, _)| *f == self.format).unwrap().1)
+                .show_ui(ui, |ui| {
+                    for (format, name) in FORMATS {
+                        ui.selectable_value(&mut self.format, format, name);
+                    }
+                });
+            ui.end_row();
+        });
+
+        let mut command = None;
+        ui.horizontal(|ui| {
+            for (label, seconds) in [("Last 30 s", 30), ("Last 5 min", 300)] {
+                if ui.button(label).clicked() {
+                    self.select_last(seconds, now);
+                }
+            }
+            if ui.button("Export").clicked() {
+                match (parse_time(&self.start, now), parse_time(&self.end, now)) {
+                    (Some(start), Some(end)) if start < end => {
+                        self.error = None;
+                        command = Some(Command::ExportRange {
+                            start,
+                            end,
+                            format: self.format,
+                        });
+                    }
+                    (Some(_), Some(_)) => self.error = Some("Start must be before end".into()),
+                    _ => self.error = Some("Times must be HH:MM:SS".into()),
+                }
+            }
+        });
+        if let Some(error) = &self.error {
+            ui.colored_label(RED, error);
+        }
+        command
+    }
+}
+
+/// Parses a time of day as the most recent time it was.
+fn parse_time(text: &str, now: OffsetDateTime) -> Option<OffsetDateTime> {
+    let time = Time::parse(text.trim(), TIME_FORMAT).ok()?;
+    let timestamp = now.replace_time(time);
+    if timestamp > now {
+        Some(timestamp - Duration::days(1))
+    } else {
+        Some(timestamp)
+    }
+}
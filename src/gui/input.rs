@@ -0,0 +1,355 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+
+use anyhow::{Context, Result};
+use eframe::egui::{
+    self,
+    text::{CCursor, CCursorRange},
+    widgets::text_edit::TextEditState,
+    Key, Modifiers,
+};
+
+use crate::log::{Log, Origin};
+
+/// Number of commands to keep in the history file.
+const MAX_HISTORY: usize = 1_000;
+
+/// Number of completions to show above the input.
+const MAX_SUGGESTIONS: usize = 8;
+
+/// Prefix of a firmware log line listing the commands the vehicle
+/// understands, e.g. `commands: arm, disarm, surge`.
+const VOCABULARY_PREFIX: &str = "commands:";
+
+/// An in-progress reverse search through the history.
+struct Search {
+    query: String,
+    /// Only commands before this index are searched, so that
+    /// searching again finds older matches.
+    before: usize,
+}
+
+/// The command line at the bottom of the window, with shell-like
+/// history.
+pub struct CommandInput {
+    text: String,
+    /// Previous commands, oldest first.
+    history: Vec<String>,
+    /// Index of the command recalled with the arrow keys, if any.
+    position: Option<usize>,
+    /// What was typed before recalling commands.
+    draft: String,
+    search: Option<Search>,
+    history_file: PathBuf,
+    /// Preset commands from the config.
+    commands: Vec<String>,
+    /// Commands the vehicle told us about.
+    vocabulary: Vec<String>,
+}
+
+impl CommandInput {
+    /// Creates an input with an empty history, which is persisted to
+    /// `history_file`, completing the given preset `commands`.
+    pub fn new(history_file: PathBuf, commands: Vec<String>) -> Self {
+        Self {
+            text: String::new(),
+            history: Vec::new(),
+            position: None,
+            draft: String::new(),
+            search: None,
+            history_file,
+            commands,
+            vocabulary: Vec::new(),
+        }
+    }
+
+    /// Creates an input with the history from `history_file`, if it
+    /// exists.
+    pub fn load(history_file: PathBuf, commands: Vec<String>) -> Result<Self> {
+        let mut input = Self::new(history_file, commands);
+        let contents = match fs::read_to_string(&input.history_file) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(input),
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read file: {}", input.history_file.display())
+                })
+            }
+        };
+        input.history = contents
+            .lines()
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect();
+        if input.history.len() > MAX_HISTORY {
+            input.history.drain(..input.history.len() - MAX_HISTORY);
+            let mut contents = input.history.join("\n");
+            contents.push('\n');
+            fs::write(&input.history_file, contents).with_context(|| {
+                format!("Failed to write file: {}", input.history_file.display())
+            })?;
+        }
+        Ok(input)
+    }
+
+    /// Adds a command to the history and the history file, unless it
+    /// is the same as the previous one.
+    pub fn remember(&mut self, command: &str) -> Result<()> {
+        if command.is_empty() || self.history.last().map(String::as_str) == Some(command) {
+            return Ok(());
+        }
+        self.history.push(command.to_string());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.history_file)
+            .with_context(|| format!("Failed to open file: {}", self.history_file.display()))?;
+        writeln!(file, "{command}")
+            .with_context(|| format!("Failed to write file: {}", self.history_file.display()))
+    }
+
+    /// Picks up the commands the vehicle understands from a firmware
+    /// log line listing them.
+    pub fn learn(&mut self, log: &Log) {
+        if log.origin != Origin::Firmware {
+            return;
+        }
+        if let Some(commands) = log.message.strip_prefix(VOCABULARY_PREFIX) {
+            self.vocabulary = commands
+                .split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+    }
+
+    /// Known commands that start with the text so far, presets first,
+    /// then the vehicle's, then the most recently used.
+    fn suggestions(&self) -> Vec<&str> {
+        let mut suggestions = Vec::new();
+        if self.text.is_empty() {
+            return suggestions;
+        }
+        let known = self
+            .commands
+            .iter()
+            .chain(&self.vocabulary)
+            .chain(self.history.iter().rev());
+        for command in known {
+            if command.starts_with(&self.text)
+                && *command != self.text
+                && !suggestions.contains(&command.as_str())
+            {
+                suggestions.push(command.as_str());
+            }
+        }
+        suggestions
+    }
+
+    /// Extends the text as far as all suggestions agree, which
+    /// completes it if there is only one.
+    fn complete(&mut self) {
+        let suggestions = self.suggestions();
+        let Some(first) = suggestions.first() else {
+            return;
+        };
+        let common = suggestions
+            .iter()
+            .fold(first.len(), |len, s| common_prefix(&first[..len], s));
+        self.text = first[..common].to_string();
+    }
+
+    /// Index of the newest command matching the current search.
+    fn search_match(&self) -> Option<usize> {
+        let search = self.search.as_ref()?;
+        self.history[..search.before]
+            .iter()
+            .rposition(|c| c.contains(&search.query))
+    }
+
+    /// Replaces the text with the given command from the history.
+    fn recall(&mut self, position: Option<usize>) {
+        self.position = position;
+        self.text = match position {
+            Some(position) => self.history[position].clone(),
+            None => self.draft.clone(),
+        };
+    }
+
+    fn previous(&mut self) {
+        match self.position {
+            Some(position) => self.recall(Some(position.saturating_sub(1))),
+            None if !self.history.is_empty() => {
+                self.draft = self.text.clone();
+                self.recall(Some(self.history.len() - 1));
+            }
+            None => {}
+        }
+    }
+
+    fn next(&mut self) {
+        if let Some(position) = self.position {
+            if position + 1 < self.history.len() {
+                self.recall(Some(position + 1));
+            } else {
+                self.recall(None);
+            }
+        }
+    }
+
+    /// Ends the search, keeping the match if there is one.
+    fn accept_search(&mut self) {
+        if let Some(position) = self.search_match() {
+            self.text = self.history[position].clone();
+            self.position = Some(position);
+        }
+        self.search = None;
+    }
+
+    /// Draws the input, returning a command if the operator entered
+    /// one.
+    pub fn draw(&mut self, ui: &mut egui::Ui) -> Option<String> {
+        let id = egui::Id::new("Command input");
+        let focused = ui.memory(|m| m.has_focus(id));
+        let (up, down, search, tab, escape) = ui.input_mut(|i| {
+            if !focused {
+                return (false, false, false, false, i.key_pressed(Key::Escape));
+            }
+            (
+                i.consume_key(Modifiers::NONE, Key::ArrowUp),
+                i.consume_key(Modifiers::NONE, Key::ArrowDown),
+                i.consume_key(Modifiers::CTRL, Key::R),
+                i.consume_key(Modifiers::NONE, Key::Tab),
+                i.key_pressed(Key::Escape),
+            )
+        });
+
+        let mut recalled = false;
+        if escape && self.search.is_some() {
+            self.search = None;
+            ui.memory_mut(|m| m.request_focus(id));
+        } else if search {
+            // Searching again skips the current match
+            let before = self.search_match().unwrap_or(self.history.len());
+            self.search
+                .get_or_insert(Search {
+                    query: String::new(),
+                    before,
+                })
+                .before = before;
+        } else if (up || down) && self.search.is_some() {
+            self.accept_search();
+            recalled = true;
+        } else if up {
+            self.previous();
+            recalled = true;
+        } else if down {
+            self.next();
+            recalled = true;
+        } else if tab && self.search.is_none() {
+            self.complete();
+            recalled = true;
+        }
+
+        let mut command = None;
+        let history_len = self.history.len();
+        let found = self.search_match().map(|p| self.history[p].clone());
+        let response = match &mut self.search {
+            Some(search) => {
+                ui.horizontal(|ui| {
+                    ui.label("(reverse-i-search)");
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut search.query)
+                            .id(id)
+                            .desired_width(200.),
+                    );
+                    if response.changed() {
+                        search.before = history_len;
+                    }
+                    ui.label(egui::RichText::new(found.unwrap_or_default()).monospace());
+                    response
+                })
+                .inner
+            }
+            None => ui.add(
+                egui::TextEdit::singleline(&mut self.text)
+                    .id(id)
+                    .hint_text("Tab to complete, Up/Down to recall commands, Ctrl+R to search")
+                    .lock_focus(true)
+                    .desired_width(ui.available_width()),
+            ),
+        };
+
+        if focused && self.search.is_none() {
+            if let Some(suggestion) = self.draw_suggestions(ui, &response) {
+                self.text = suggestion;
+                response.request_focus();
+                recalled = true;
+            }
+        }
+
+        if response.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter)) {
+            if self.search.is_some() {
+                self.accept_search();
+                recalled = true;
+            } else {
+                command = Some(std::mem::take(&mut self.text));
+                self.position = None;
+                self.draft.clear();
+            }
+            response.request_focus();
+        }
+
+        if recalled {
+            let mut state = TextEditState::load(ui.ctx(), id).unwrap_or_default();
+            let end = CCursor::new(self.text.chars().count());
+            state.set_ccursor_range(Some(CCursorRange::one(end)));
+            state.store(ui.ctx(), id);
+        }
+        command
+    }
+
+    /// Shows the completions above the input, returning the one the
+    /// operator clicked on.
+    fn draw_suggestions(&self, ui: &egui::Ui, input: &egui::Response) -> Option<String> {
+        let suggestions = self.suggestions();
+        if suggestions.is_empty() {
+            return None;
+        }
+        egui::Area::new("Command suggestions")
+            .order(egui::Order::Foreground)
+            .fixed_pos(input.rect.left_top())
+            .pivot(egui::Align2::LEFT_BOTTOM)
+            .show(ui.ctx(), |ui| {
+                egui::Frame::popup(ui.style())
+                    .show(ui, |ui| {
+                        let mut clicked = None;
+                        for suggestion in suggestions.iter().take(MAX_SUGGESTIONS) {
+                            let text = egui::RichText::new(*suggestion).monospace();
+                            if ui.selectable_label(false, text).clicked() {
+                                clicked = Some(suggestion.to_string());
+                            }
+                        }
+                        if suggestions.len() > MAX_SUGGESTIONS {
+                            ui.weak(format!("{} more", suggestions.len() - MAX_SUGGESTIONS));
+                        }
+                        clicked
+                    })
+                    .inner
+            })
+            .inner
+    }
+}
+
+/// Length in bytes of the prefix two strings have in common.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map(|((i, _), _)| i)
+        .unwrap_or(a.len().min(b.len()))
+}
@@ -0,0 +1,163 @@
+use eframe::egui;
+use time::OffsetDateTime;
+
+use crate::gui::color::{GREEN, RED, YELLOW};
+use crate::serial::available_ports;
+use crate::source::{LinkStats, LinkStatus};
+
+/// Keeps track of the state of the link to the vehicle, so that a
+/// dead link can be told apart from a silent vehicle.
+#[derive(Default)]
+pub struct LinkMonitor {
+    status: Option<LinkStatus>,
+    stats: LinkStats,
+    last_stats: Option<OffsetDateTime>,
+    bytes_per_second: f64,
+    packets_per_second: f64,
+}
+
+impl LinkMonitor {
+    pub fn set_status(&mut self, status: LinkStatus) {
+        if let LinkStatus::Disconnected { .. } = status {
+            self.bytes_per_second = 0.;
+            self.packets_per_second = 0.;
+            self.last_stats = None;
+        }
+        self.status = Some(status);
+    }
+
+    pub fn add_stats(&mut self, stats: LinkStats, now: OffsetDateTime) {
+        if let Some(last_stats) = self.last_stats {
+            let elapsed = (now - last_stats).as_seconds_f64();
+            if elapsed > 0. {
+                self.bytes_per_second =
+                    stats.bytes.saturating_sub(self.stats.bytes) as f64 / elapsed;
+                self.packets_per_second =
+                    stats.packets.saturating_sub(self.stats.packets) as f64 / elapsed;
+            }
+        }
+        self.stats = stats;
+        self.last_stats = Some(now);
+    }
+
+    pub fn draw(&self, ui: &mut egui::Ui) {
+        let (label, color) = match &self.status {
+            Some(LinkStatus::Connected { link }) => {
+                ui.label(format!("Link: {link}"));
+                ("CONNECTED".to_string(), GREEN)
+            }
+            Some(LinkStatus::Disconnected { link, retries, .. }) => {
+                ui.label(format!("Link: {link}"));
+                if *retries > 0 {
+                    (format!("RECONNECTING ({retries})"), RED)
+                } else {
+                    ("DISCONNECTED".to_string(), RED)
+                }
+            }
+            None => {
+                ui.label("Link:");
+                ("NO LINK".to_string(), YELLOW)
+            }
+        };
+        let response = ui.label(
+            egui::RichText::new(label)
+                .background_color(color)
+                .color(egui::Color32::BLACK)
+                .strong(),
+        );
+        if let Some(LinkStatus::Disconnected { reason, .. }) = &self.status {
+            response.on_hover_text(reason);
+        }
+
+        ui.separator();
+        ui.label(format!("RX: {:.0} B/s", self.bytes_per_second));
+        ui.label(format!("{:.1} pkt/s", self.packets_per_second));
+        ui.label(format!("ERR: {}", self.stats.decode_errors()));
+    }
+
+    /// Draws the decoder health counters and the byte sequences it
+    /// most recently rejected.
+    pub fn draw_decoder(&self, ui: &mut egui::Ui) {
+        egui::Grid::new("Decoder counters").show(ui, |ui| {
+            for (name, value) in [
+                ("Bytes received", self.stats.bytes),
+                ("Packages decoded", self.stats.packets),
+                ("Bad encodings", self.stats.bad_encodings),
+                ("Unexpected ends", self.stats.unexpected_ends),
+                ("Unknown packages", self.stats.unknown_packages),
+                ("Buffer overflows", self.stats.overflows),
+            ] {
+                ui.label(name);
+                ui.label(value.to_string());
+                ui.end_row();
+            }
+        });
+
+        ui.separator();
+        ui.label("Rejected bytes, newest first:");
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for bytes in self.stats.rejected.iter().rev() {
+                ui.monospace(
+                    bytes
+                        .iter()
+                        .map(|b| format!("{b:02x}"))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                );
+            }
+        });
+    }
+}
+
+/// Common serial port baud rates.
+const BAUD_RATES: [u32; 8] = [9600, 19200, 38400, 57600, 115200, 230400, 460800, 921600];
+
+/// Lets the operator switch the serial port the link uses without
+/// restarting.
+pub struct PortPicker {
+    ports: Vec<String>,
+    path: String,
+    baud: u32,
+}
+
+impl PortPicker {
+    pub fn new(path: &str, baud: u32) -> Self {
+        Self {
+            ports: available_ports(),
+            path: path.to_string(),
+            baud,
+        }
+    }
+
+    /// Draws the picker, returning the selected port and baud rate
+    /// if the operator asked to connect.
+    pub fn draw(&mut self, ui: &mut egui::Ui) -> Option<(String, u32)> {
+        egui::ComboBox::from_id_source("Port")
+            .selected_text(&self.path)
+            .width(ui.available_width())
+            .show_ui(ui, |ui| {
+                for port in &self.ports {
+                    ui.selectable_value(&mut self.path, port.clone(), port);
+                }
+            });
+        egui::ComboBox::from_id_source("Baud")
+            .selected_text(self.baud.to_string())
+            .width(ui.available_width())
+            .show_ui(ui, |ui| {
+                for baud in BAUD_RATES {
+                    ui.selectable_value(&mut self.baud, baud, baud.to_string());
+                }
+            });
+
+        let mut selected = None;
+        ui.horizontal(|ui| {
+            if ui.button("Refresh").clicked() {
+                self.ports = available_ports();
+            }
+            if ui.button("Connect").clicked() {
+                selected = Some((self.path.clone(), self.baud));
+            }
+        });
+        selected
+    }
+}
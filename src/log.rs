@@ -0,0 +1,147 @@
+use std::fmt;
+
+use time::OffsetDateTime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Debug,
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub const ALL: [Self; 4] = [Self::Debug, Self::Info, Self::Warning, Self::Error];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "debug" | "trace" => Some(Self::Debug),
+            "info" => Some(Self::Info),
+            "warn" | "warning" => Some(Self::Warning),
+            "error" | "err" | "fatal" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Debug => write!(f, "DEBUG"),
+            Self::Info => write!(f, "INFO"),
+            Self::Warning => write!(f, "WARN"),
+            Self::Error => write!(f, "ERROR"),
+        }
+    }
+}
+
+/// Where a log message came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// Sent by the vehicle.
+    Firmware,
+    /// Mission control itself.
+    System,
+    /// The link to the vehicle.
+    Link,
+}
+
+impl Origin {
+    pub const ALL: [Self; 3] = [Self::Firmware, Self::System, Self::Link];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "FIRMWARE" => Some(Self::Firmware),
+            "SYSTEM" => Some(Self::System),
+            "LINK" => Some(Self::Link),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Firmware => write!(f, "FIRMWARE"),
+            Self::System => write!(f, "SYSTEM"),
+            Self::Link => write!(f, "LINK"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Log {
+    pub timestamp: OffsetDateTime,
+    pub severity: Severity,
+    pub origin: Origin,
+    pub message: String,
+}
+
+impl Log {
+    pub fn new(origin: Origin, severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_local().unwrap(),
+            severity,
+            origin,
+            message: message.into(),
+        }
+    }
+
+    pub fn info(origin: Origin, message: impl Into<String>) -> Self {
+        Self::new(origin, Severity::Info, message)
+    }
+
+    pub fn warning(origin: Origin, message: impl Into<String>) -> Self {
+        Self::new(origin, Severity::Warning, message)
+    }
+
+    pub fn error(origin: Origin, message: impl Into<String>) -> Self {
+        Self::new(origin, Severity::Error, message)
+    }
+
+    /// A log line sent by the vehicle, which may start with its
+    /// severity, e.g. `[WARN] ...` or `ERROR: ...`.
+    pub fn firmware(line: &str) -> Self {
+        match split_severity(line) {
+            Some((severity, message)) => Self::new(Origin::Firmware, severity, message),
+            None => Self::info(Origin::Firmware, line),
+        }
+    }
+
+    /// Parses a log line as formatted by `Display`, e.g. from a
+    /// recorded session. Anything else is taken to be an info message
+    /// from the system.
+    pub fn parse(timestamp: OffsetDateTime, line: &str) -> Self {
+        let parsed = line.split_once(' ').and_then(|(severity, rest)| {
+            let (origin, message) = rest.strip_prefix('[')?.split_once("] ")?;
+            Some((
+                Severity::from_name(severity)?,
+                Origin::from_name(origin)?,
+                message,
+            ))
+        });
+        let (severity, origin, message) = parsed.unwrap_or((Severity::Info, Origin::System, line));
+        Self {
+            timestamp,
+            severity,
+            origin,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Log {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} [{}] {}", self.severity, self.origin, self.message)
+    }
+}
+
+/// Splits a leading severity like `[WARN]` or `ERROR:` off a line.
+fn split_severity(line: &str) -> Option<(Severity, &str)> {
+    let line = line.trim_start();
+    let (name, message) = match line.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?,
+        None => line.split_once(':')?,
+    };
+    Some((Severity::from_name(name.trim())?, message.trim_start()))
+}
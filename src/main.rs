@@ -1,36 +1,96 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, process, time::Duration};
 
-use anyhow::Result;
-use clap::Parser;
-use tokio::{sync::broadcast, task};
+use anyhow::{ensure, Result};
+use clap::{Parser, Subcommand};
+use time::OffsetDateTime;
+#[cfg(unix)]
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+};
+use tokio::{signal::ctrl_c, task, time::sleep};
 
+mod bus;
+mod capture;
 mod config;
 mod gui;
+mod log;
 mod recorder;
+mod replay;
 mod serial;
+mod source;
+mod tcp;
 mod telemetry;
+mod udp;
 
+use bus::Bus;
+use config::Format;
+use log::{Log, Origin};
 use recorder::Recorder;
+use replay::Replay;
+use source::{CommandSent, LinkStats, LinkStatus};
 use telemetry::Frame;
 
+/// How long the recorder gets to save the session after a second
+/// shutdown signal, before exiting regardless.
+const FORCE_EXIT_TIMEOUT: Duration = Duration::from_secs(3);
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Config file path
     #[clap(short, long, default_value = "mctl.toml")]
     config: PathBuf,
+
+    #[command(subcommand)]
+    mode: Option<Mode>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Mode {
+    /// Play back an exported CSV session or a raw capture instead of
+    /// connecting to a vehicle
+    Replay {
+        /// Exported CSV file, or capture file with `--raw`
+        file: PathBuf,
+
+        /// Replay a raw capture through the decoder
+        #[clap(long)]
+        raw: bool,
+
+        /// Playback speed multiplier
+        #[clap(short, long, default_value_t = 1.0)]
+        speed: f64,
+
+        /// Record the replay as a new session, like a live link
+        #[clap(long)]
+        record: bool,
+    },
 }
 
 #[derive(Clone, Debug)]
 pub enum Message {
     Command(Command),
     Telemetry(Frame),
-    Log(String),
+    Log(Log),
+    Link(LinkStatus),
+    CommandSent(CommandSent),
+    LinkStats(LinkStats),
 }
 
 #[derive(Clone, Debug)]
 pub enum Command {
     SendCommand(String),
+    SelectPort {
+        path: String,
+        baud: u32,
+    },
+    SetNotes(String),
+    ExportRange {
+        start: OffsetDateTime,
+        end: OffsetDateTime,
+        format: Format,
+    },
     Export,
     Reset,
     Exit,
@@ -41,35 +101,91 @@ async fn main() -> Result<()> {
     let args = Args::parse();
     let config = config::load_config(&args.config)?;
 
-    let (recorder_tx, recorder_rx) = broadcast::channel::<Message>(128);
-    let gui_tx = recorder_tx.clone();
-    let gui_rx = recorder_tx.subscribe();
-    let serial_sender_tx = recorder_tx.clone();
-    let serial_sender_rx = recorder_tx.subscribe();
-    let serial_listener_tx = recorder_tx.clone();
+    let (bus, recorder_rx) = Bus::new(128);
+    let recorder_tx = bus.clone();
+    let gui_rx = bus.subscribe();
+    let sink_tx = bus.clone();
+    let sink_rx = bus.subscribe();
+    let source_tx = bus.clone();
+
+    // Replays are already on disk, don't record them again unless asked
+    // to.
+    let record = match &args.mode {
+        Some(Mode::Replay { record, .. }) => *record,
+        None => true,
+    };
+    let recorder_task = record.then(|| {
+        let mut recorder = Recorder::new(config.clone());
+        task::spawn(async move { recorder.run(recorder_rx, recorder_tx).await })
+    });
 
-    let mut recorder = Recorder::new();
-    task::spawn(async move { recorder.run(recorder_rx, recorder_tx).await });
+    let (mut source, mut sink) = match &args.mode {
+        Some(Mode::Replay {
+            file, raw, speed, ..
+        }) => {
+            ensure!(*speed > 0., "replay speed must be positive");
+            source::split(Replay::new(file, *raw, *speed))
+        }
+        None => source::open(&config),
+    };
 
-    let baud_rate = config.serial.baud;
+    let sink_task = task::spawn(async move {
+        sink.send_commands(sink_rx, sink_tx)
+            .await
+            .expect("failed to open link for sending commands")
+    });
 
-    let serial_path = config.serial.path.clone();
-    task::spawn(async move {
-        serial::send_commands(&serial_path, baud_rate, serial_sender_rx, serial_sender_tx)
+    let source_task = task::spawn(async move {
+        source
+            .listen(source_tx)
             .await
-            .expect("failed to open serial port for sending commands")
+            .expect("failed to open link for listening")
     });
 
-    let serial_path = config.serial.path.clone();
+    let signal_tx = bus.clone();
     task::spawn(async move {
-        serial::listen(&serial_path, baud_rate, serial_listener_tx)
-            .await
-            .expect("failed to open serial port for listening")
+        shutdown_signal().await;
+        signal_tx.send(Message::Command(Command::Exit));
+        // If shutting down hangs, another signal forces the exit.
+        shutdown_signal().await;
+        println!(
+            "{}",
+            Log::warning(
+                Origin::System,
+                format!("Exiting in {}s", FORCE_EXIT_TIMEOUT.as_secs()),
+            )
+        );
+        sleep(FORCE_EXIT_TIMEOUT).await;
+        process::exit(1);
     });
 
-    gui::run(config, gui_rx, gui_tx)?;
+    let replay = args.mode.is_some();
+    let result = gui::run(config, replay, record, gui_rx, bus.clone());
+
+    // The window has been closed, or failed, let the recorder save
+    // everything before closing the link.
+    bus.send(Message::Command(Command::Exit));
+    if let Some(recorder_task) = recorder_task {
+        recorder_task.await?;
+    }
+    source_task.abort();
+    sink_task.abort();
+
+    result
+}
 
-    Ok(())
+/// Waits for SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+        select! {
+            _ = ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = ctrl_c().await;
 }
 
 // Use this patch for serial-rs to support fake serial ports on macOS
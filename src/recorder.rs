@@ -1,120 +1,528 @@
-use anyhow::{Context, Result};
-use csv::Writer;
-use time::{macros::format_description, OffsetDateTime};
-use tokio::sync::broadcast::{Receiver, Sender};
+use std::{collections::VecDeque, fs, path::PathBuf, time::Instant};
 
-use crate::{telemetry::Frame, Command, Message};
+use anyhow::{ensure, Context, Result};
+use time::{format_description::FormatItem, macros::format_description, OffsetDateTime};
+use tokio::{select, sync::mpsc::UnboundedReceiver, time::interval};
 
-/// At some point we'll run out of memory, so flush to disk every now
-/// and then.
-const MAX_FRAMES: usize = 90_000;
+mod database;
+mod format;
+mod history;
+mod mcap;
+mod metadata;
 
+use crate::{
+    bus::Bus,
+    config::{Config, Format},
+    log::{Log, Origin},
+    telemetry::Frame,
+    Command, Message,
+};
+use format::FrameWriter;
+use history::History;
+use metadata::{format_time, Metadata};
+
+/// Number of frames to collect before deciding on the columns of a
+/// session file, so that data points which aren't in every frame
+/// still make it into the header. The columns are decided at the next
+/// sync at the latest.
+const HEADER_FRAMES: usize = 50;
+
+/// Number of logs to keep for the next session while none is open.
+const MAX_PENDING_LOGS: usize = 1_000;
+
+/// How often to make sure the session file is actually on disk.
+const SYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Formats of the `{date}` and `{time}` file name tokens, avoiding
+/// characters which aren't allowed in file names everywhere.
+const DATE_FORMAT: &[FormatItem<'_>] = format_description!("[year]-[month]-[day]");
+const TIME_FORMAT: &[FormatItem<'_>] = format_description!("[hour]-[minute]-[second]");
+
+/// Format of the timestamp column in exported files.
+pub const TIMESTAMP_FORMAT: &[FormatItem<'_>] =
+    format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]");
+
+/// Names of the columns logs and commands are recorded in, in formats
+/// which record everything in one table.
+pub const LOG_COLUMN: &str = "log";
+pub const COMMAND_COLUMN: &str = "command";
+
+/// Streams everything received to a session file on disk, so that
+/// memory use stays flat and a crash loses at most a few seconds of
+/// data.
 pub struct Recorder {
-    frames: Vec<Frame>,
+    config: Config,
+    /// Operator notes for the current session.
+    notes: String,
+    session: Option<Session>,
+    /// Logs received while no session was open.
+    pending_logs: VecDeque<Log>,
+    history: History,
 }
 
 impl Recorder {
-    pub fn new() -> Self {
-        Self { frames: vec![] }
+    pub fn new(config: Config) -> Self {
+        Self {
+            notes: String::new(),
+            session: None,
+            pending_logs: VecDeque::new(),
+            history: History::new(config.recording.history),
+            config,
+        }
     }
 
-    pub async fn run(&mut self, mut rx: Receiver<Message>, tx: Sender<Message>) {
+    /// Records messages until told to exit. Receives on its own
+    /// channel, so no data is lost if the recorder falls behind.
+    pub async fn run(&mut self, mut rx: UnboundedReceiver<Message>, tx: Bus) {
+        let mut sync_interval = interval(SYNC_INTERVAL);
         loop {
-            if let Ok(msg) = rx.recv().await {
-                match msg {
-                    Message::Telemetry(frame) => {
-                        self.frames.push(frame);
-                        if self.frames.len() >= MAX_FRAMES {
-                            match self.export() {
-                                Ok(_) => {
-                                    let _ = tx.send(Message::Log(
-                                        "[SYSTEM] Auto-exported data".to_string(),
-                                    ));
-                                    self.reset();
-                                }
-                                Err(e) => {
-                                    let _ = tx.send(Message::Log(format!(
-                                        "[SYSTEM] Failed to auto-export data: {e}"
-                                    )));
-                                }
-                            }
+            let msg = select! {
+                msg = rx.recv() => match msg {
+                    Some(msg) => msg,
+                    None => return,
+                },
+                // Also sync when nothing arrives, so that the last data
+                // before the vehicle went quiet is safe.
+                _ = sync_interval.tick() => {
+                    self.sync(&tx);
+                    continue;
+                }
+            };
+            match msg {
+                Message::Telemetry(frame) => self.record(Entry::Frame(frame), &tx),
+                Message::Command(cmd) => match cmd {
+                    Command::SelectPort { path, baud } => {
+                        // Keep the metadata truthful about where the
+                        // data came from.
+                        if let Some(session) = &mut self.session {
+                            session.metadata.config.select_port(&path, baud);
+                        }
+                        self.config.select_port(&path, baud);
+                    }
+                    Command::SetNotes(notes) => {
+                        if let Some(session) = &mut self.session {
+                            session.metadata.notes = notes.clone();
                         }
+                        self.notes = notes;
                     }
-                    Message::Command(cmd) => match cmd {
-                        Command::Export => match self.export() {
-                            Ok(path) => {
-                                let _ = tx.send(Message::Log(format!(
-                                    "[SYSTEM] Exported data to {path}"
-                                )));
+                    Command::Export => notify(
+                        &tx,
+                        match self.export() {
+                            Ok(Some(path)) => {
+                                Log::info(Origin::System, format!("Exported data to {path}"))
                             }
+                            Ok(None) => Log::info(Origin::System, "No data to export"),
                             Err(e) => {
-                                let _ = tx.send(Message::Log(format!(
-                                    "[SYSTEM] Failed to export data: {e}"
-                                )));
+                                Log::error(Origin::System, format!("Failed to export data: {e}"))
                             }
                         },
-                        Command::Reset => {
-                            self.reset();
+                    ),
+                    Command::ExportRange { start, end, format } => notify(
+                        &tx,
+                        match self.export_range(start, end, format) {
+                            Ok(Some(path)) => {
+                                Log::info(Origin::System, format!("Exported time range to {path}"))
+                            }
+                            Ok(None) => {
+                                Log::info(Origin::System, "No data in time range to export")
+                            }
+                            Err(e) => Log::error(
+                                Origin::System,
+                                format!("Failed to export time range: {e}"),
+                            ),
+                        },
+                    ),
+                    Command::Reset => {
+                        if let Err(e) = self.reset() {
+                            tx.send(Message::Log(Log::error(
+                                Origin::System,
+                                format!("Failed to reset: {e}"),
+                            )));
                         }
-                        Command::Exit => {
-                            return;
+                    }
+                    Command::Exit => {
+                        match self.export() {
+                            Ok(Some(path)) => println!(
+                                "{}",
+                                Log::info(Origin::System, format!("Saved data to {path}"))
+                            ),
+                            Ok(None) => {}
+                            Err(e) => println!(
+                                "{}",
+                                Log::error(Origin::System, format!("Failed to save data: {e}"))
+                            ),
                         }
-                        _ => {}
-                    },
-                    Message::Log(log) => {
-                        println!("{log}");
+                        return;
                     }
+                    _ => {}
+                },
+                Message::Log(log) => {
+                    println!("{log}");
+                    self.record_log(log, &tx);
+                }
+                Message::Link(status) => {
+                    let log = status.to_log();
+                    println!("{log}");
+                    self.record_log(log, &tx);
                 }
+                Message::CommandSent(sent) => {
+                    let log = sent.to_log();
+                    println!("{log}");
+                    // Only commands which actually went out are part
+                    // of the session.
+                    if sent.result.is_ok() {
+                        self.record(Entry::Command(sent.timestamp, sent.command), &tx);
+                    }
+                    self.record_log(log, &tx);
+                }
+                Message::LinkStats(_) => {}
             }
         }
     }
 
-    fn reset(&mut self) {
-        self.frames.clear();
+    /// Records an entry in the current session, starting a new one
+    /// if needed.
+    fn record(&mut self, entry: Entry, tx: &Bus) {
+        self.history.push(&entry);
+        if let Err(e) = self.write(entry, tx) {
+            self.session = None;
+            tx.send(Message::Log(Log::error(
+                Origin::System,
+                format!("Failed to record data: {e}"),
+            )));
+        }
     }
 
-    fn export(&self) -> Result<String> {
-        let path = format!(
-            "mctl-{}.csv",
-            OffsetDateTime::now_local()
-                .unwrap()
-                .format(&format_description!(
-                    "[year]-[month]-[day]T[hour]:[minute]:[second]"
-                ))
-                .unwrap()
-        );
-        let mut wtr =
-            Writer::from_path(&path).with_context(|| format!("Failed to open file: {}", path))?;
-
-        let mut headers = vec!["timestamp".to_string()];
-        for frame in &self.frames {
-            for data_point in &frame.data_points {
-                if !headers.contains(&data_point.name) {
-                    headers.push(data_point.name.clone());
+    /// Gets the current session onto disk, deciding on its columns if
+    /// that hasn't happened yet.
+    fn sync(&mut self, tx: &Bus) {
+        let Some(session) = &mut self.session else {
+            return;
+        };
+        let result = match session.writer {
+            None if !session.pending.is_empty() => session.write_header(),
+            Some(_) if session.unsynced => session.sync(),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.session = None;
+            tx.send(Message::Log(Log::error(
+                Origin::System,
+                format!("Failed to record data: {e}"),
+            )));
+        }
+    }
+
+    /// Logs don't start a session by themselves, they are kept for
+    /// the next one if none is open.
+    fn record_log(&mut self, log: Log, tx: &Bus) {
+        if self.session.is_some() {
+            self.record(Entry::Log(log), tx);
+            return;
+        }
+        self.history.push(&Entry::Log(log.clone()));
+        if self.pending_logs.len() >= MAX_PENDING_LOGS {
+            self.pending_logs.pop_front();
+        }
+        self.pending_logs.push_back(log);
+    }
+
+    fn write(&mut self, entry: Entry, tx: &Bus) -> Result<()> {
+        if self.session.is_none() {
+            let mut session = Session::new(
+                &self.config,
+                self.config.recording.format,
+                &self.notes,
+                vec![],
+            )?;
+            for log in self.pending_logs.drain(..) {
+                session.write(Entry::Log(log))?;
+            }
+            self.session = Some(session);
+        }
+        let session = self.session.as_mut().unwrap();
+        if let Entry::Frame(frame) = &entry {
+            if !session.accepts(frame) {
+                // The header has already been written, so new columns
+                // have to go into a new file.
+                let mut headers = session.headers.clone();
+                add_headers(&mut headers, frame);
+                let old_session = self
+                    .session
+                    .replace(Session::new(
+                        &self.config,
+                        self.config.recording.format,
+                        &self.notes,
+                        headers,
+                    )?)
+                    .unwrap();
+                if let Some(path) = old_session.finish()? {
+                    tx.send(Message::Log(Log::info(
+                        Origin::System,
+                        format!("Got new data points, split session after {path}"),
+                    )));
                 }
             }
         }
-        wtr.write_record(&headers)?;
-
-        for frame in &self.frames {
-            let mut record = vec![frame
-                .timestamp
-                .format(&format_description!(
-                    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]"
-                ))
-                .unwrap()];
-            for data_point in &frame.data_points {
-                let index = headers.iter().position(|h| h == &data_point.name).unwrap();
-                while record.len() <= index {
-                    record.push("".to_string());
+        self.session.as_mut().unwrap().write(entry)
+    }
+
+    /// Exports the entries from the history within the time range to
+    /// a new file, leaving the current session alone.
+    fn export_range(
+        &self,
+        start: OffsetDateTime,
+        end: OffsetDateTime,
+        format: Format,
+    ) -> Result<Option<String>> {
+        ensure!(
+            format != Format::Sqlite,
+            "Can't export time ranges to the session database"
+        );
+        let (entries, headers) = self.history.range(start, end);
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let mut session = Session::new(&self.config, format, &self.notes, headers)?;
+        session.metadata.start = format_time(start);
+        session.metadata.end = Some(format_time(end));
+        for entry in entries {
+            session.write(entry)?;
+        }
+        session.finish()
+    }
+
+    /// Discards the current session.
+    fn reset(&mut self) -> Result<()> {
+        self.notes.clear();
+        self.pending_logs.clear();
+        self.history.clear();
+        if let Some(session) = self.session.take() {
+            session.discard()?;
+        }
+        Ok(())
+    }
+
+    /// Closes the current session, so that the next frame starts a
+    /// new one.
+    fn export(&mut self) -> Result<Option<String>> {
+        self.notes.clear();
+        match self.session.take() {
+            Some(session) => session.finish(),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Tells the operator about the outcome of an export, without
+/// recording it in the next session.
+fn notify(tx: &Bus, log: Log) {
+    println!("{log}");
+    tx.send_to_subscribers(Message::Log(log));
+}
+
+/// Something recorded in a session.
+#[derive(Clone)]
+enum Entry {
+    Frame(Frame),
+    Log(Log),
+    Command(OffsetDateTime, String),
+}
+
+impl Entry {
+    fn timestamp(&self) -> OffsetDateTime {
+        match self {
+            Self::Frame(frame) => frame.timestamp,
+            Self::Log(log) => log.timestamp,
+            Self::Command(timestamp, _) => *timestamp,
+        }
+    }
+}
+
+/// A session being streamed to a file with a timestamp column, log
+/// and command columns, and one column per data point.
+struct Session {
+    path: String,
+    format: Format,
+    metadata: Metadata,
+    headers: Vec<String>,
+    /// Entries waiting for the columns to be decided.
+    pending: Vec<Entry>,
+    /// Only present once the columns have been decided.
+    writer: Option<Box<dyn FrameWriter>>,
+    last_sync: Instant,
+    /// Whether anything has been written since the last sync.
+    unsynced: bool,
+}
+
+impl Session {
+    fn new(config: &Config, format: Format, notes: &str, headers: Vec<String>) -> Result<Self> {
+        let files = FileTemplate::new(config);
+        let path = match format {
+            Format::Sqlite => files.fixed_path(&config.recording.database)?,
+            format => files.path(format.extension())?,
+        };
+        Ok(Self {
+            path,
+            format,
+            metadata: Metadata::new(config, notes),
+            headers,
+            pending: vec![],
+            writer: None,
+            last_sync: Instant::now(),
+            unsynced: false,
+        })
+    }
+
+    /// Whether the frame fits into the file's columns.
+    fn accepts(&self, frame: &Frame) -> bool {
+        self.writer.is_none()
+            || !self.format.fixed_columns()
+            || frame
+                .data_points
+                .iter()
+                .all(|dp| self.headers.contains(&dp.name))
+    }
+
+    fn write(&mut self, entry: Entry) -> Result<()> {
+        match &mut self.writer {
+            Some(writer) => {
+                write_entry(writer.as_mut(), entry)?;
+                self.unsynced = true;
+                if self.last_sync.elapsed() >= SYNC_INTERVAL {
+                    self.sync()?;
                 }
-                record[index] = data_point.value.to_string();
             }
-            wtr.write_record(record)?;
+            None => {
+                self.pending.push(entry);
+                let frames = self
+                    .pending
+                    .iter()
+                    .filter(|e| matches!(e, Entry::Frame(_)))
+                    .count();
+                if frames >= HEADER_FRAMES || !self.format.fixed_columns() {
+                    self.write_header()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Opens the file with the columns seen so far and writes all
+    /// pending entries.
+    fn write_header(&mut self) -> Result<()> {
+        for entry in &self.pending {
+            if let Entry::Frame(frame) = entry {
+                add_headers(&mut self.headers, frame);
+            }
         }
+        let mut writer = self.format.create(&self.path, &self.headers)?;
+        writer.write_metadata(&self.metadata)?;
+        for entry in self.pending.drain(..) {
+            write_entry(writer.as_mut(), entry)?;
+        }
+        self.writer = Some(writer);
+        self.sync()
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        if let Some(writer) = &mut self.writer {
+            writer.sync()?;
+        }
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
 
-        wtr.flush()?;
+    /// Writes out everything and closes the file, returning its path
+    /// if anything was recorded.
+    fn finish(mut self) -> Result<Option<String>> {
+        if self.writer.is_none() {
+            if self.pending.is_empty() {
+                return Ok(None);
+            }
+            self.write_header()?;
+        }
+        let mut writer = self.writer.unwrap();
+        self.metadata.close();
+        writer.write_metadata(&self.metadata)?;
+        writer.finish()?;
+        Ok(Some(self.path))
+    }
+
+    /// Throws away everything recorded.
+    fn discard(self) -> Result<()> {
+        match self.writer {
+            Some(writer) => writer.discard(),
+            None => Ok(()),
+        }
+    }
+}
+
+fn write_entry(writer: &mut dyn FrameWriter, entry: Entry) -> Result<()> {
+    match entry {
+        Entry::Frame(frame) => writer.write_frame(frame),
+        Entry::Log(log) => writer.write_log(&log),
+        Entry::Command(timestamp, cmd) => writer.write_command(timestamp, &cmd),
+    }
+}
+
+/// Adds any new data point names in the frame to the headers.
+fn add_headers(headers: &mut Vec<String>, frame: &Frame) {
+    for data_point in &frame.data_points {
+        if !headers.contains(&data_point.name) {
+            headers.push(data_point.name.clone());
+        }
+    }
+}
+
+/// Names session files according to the `[recording]` config.
+#[derive(Clone, Debug)]
+pub struct FileTemplate {
+    directory: PathBuf,
+    template: String,
+    session: String,
+    vehicle: String,
+}
+
+impl FileTemplate {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            directory: config.recording.directory.clone(),
+            template: config.recording.template.clone(),
+            session: config.recording.session.clone(),
+            vehicle: config.vehicle.clone(),
+        }
+    }
+
+    /// Returns the path for a new session file with the given
+    /// extension, creating the directory if needed.
+    pub fn path(&self, extension: &str) -> Result<String> {
+        fs::create_dir_all(&self.directory)
+            .with_context(|| format!("Failed to create directory: {}", self.directory.display()))?;
+        let now = OffsetDateTime::now_local().unwrap();
+        let name = self
+            .template
+            .replace("{session}", &self.session)
+            .replace("{vehicle}", &self.vehicle)
+            .replace("{date}", &now.format(DATE_FORMAT)?)
+            .replace("{time}", &now.format(TIME_FORMAT)?);
+        // Sessions can be split several times a second, don't overwrite
+        // any of them.
+        let mut path = self.directory.join(format!("{name}.{extension}"));
+        let mut n = 1;
+        while path.exists() {
+            path = self.directory.join(format!("{name}-{n}.{extension}"));
+            n += 1;
+        }
+        Ok(path.to_string_lossy().into_owned())
+    }
 
-        Ok(path)
+    /// Returns the path of a file which isn't named after the
+    /// session, like a database, in the output directory.
+    pub fn fixed_path(&self, name: &str) -> Result<String> {
+        fs::create_dir_all(&self.directory)
+            .with_context(|| format!("Failed to create directory: {}", self.directory.display()))?;
+        Ok(self.directory.join(name).to_string_lossy().into_owned())
     }
 }
@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use time::OffsetDateTime;
+
+use crate::{
+    log::Log,
+    recorder::{format::FrameWriter, metadata::Metadata},
+    telemetry::Frame,
+};
+
+/// Tables are only created if they don't exist yet, so that sessions
+/// accumulate in the same database across runs.
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+
+    CREATE TABLE IF NOT EXISTS sessions (
+        id INTEGER PRIMARY KEY,
+        started_at TEXT NOT NULL,
+        ended_at TEXT,
+        metadata TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS frames (
+        id INTEGER PRIMARY KEY,
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        timestamp TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS data_points (
+        frame_id INTEGER NOT NULL REFERENCES frames (id),
+        name TEXT NOT NULL,
+        value REAL NOT NULL
+    );
+
+    CREATE INDEX IF NOT EXISTS frames_session_id ON frames (session_id);
+    CREATE INDEX IF NOT EXISTS data_points_frame_id ON data_points (frame_id);
+    CREATE INDEX IF NOT EXISTS data_points_name ON data_points (name);
+
+    CREATE TABLE IF NOT EXISTS logs (
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        timestamp TEXT NOT NULL,
+        severity TEXT NOT NULL,
+        origin TEXT NOT NULL,
+        message TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS commands (
+        session_id INTEGER NOT NULL REFERENCES sessions (id),
+        timestamp TEXT NOT NULL,
+        command TEXT NOT NULL
+    );
+";
+
+/// A session recorded into a SQLite database shared by all sessions,
+/// so that data can be queried across them.
+///
+/// Writes are batched into a transaction which is committed on every
+/// sync.
+pub struct Database {
+    conn: Connection,
+    session_id: i64,
+}
+
+impl Database {
+    /// Opens the database, creating it if needed, and starts a new
+    /// session in it.
+    pub fn create(path: &str) -> Result<Self> {
+        let conn =
+            Connection::open(path).with_context(|| format!("Failed to open database: {}", path))?;
+        conn.execute_batch(SCHEMA)?;
+        conn.execute(
+            "INSERT INTO sessions (started_at) VALUES (?1)",
+            params![OffsetDateTime::now_utc()],
+        )?;
+        let session_id = conn.last_insert_rowid();
+        conn.execute_batch("BEGIN")?;
+        Ok(Self { conn, session_id })
+    }
+}
+
+impl FrameWriter for Database {
+    fn write_frame(&mut self, frame: Frame) -> Result<()> {
+        self.conn
+            .prepare_cached("INSERT INTO frames (session_id, timestamp) VALUES (?1, ?2)")?
+            .execute(params![self.session_id, frame.timestamp])?;
+        let frame_id = self.conn.last_insert_rowid();
+        let mut insert = self.conn.prepare_cached(
+            "INSERT INTO data_points (frame_id, name, value) VALUES (?1, ?2, ?3)",
+        )?;
+        for data_point in &frame.data_points {
+            insert.execute(params![frame_id, data_point.name, data_point.value])?;
+        }
+        Ok(())
+    }
+
+    fn write_log(&mut self, log: &Log) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO logs (session_id, timestamp, severity, origin, message)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?
+            .execute(params![
+                self.session_id,
+                log.timestamp,
+                log.severity.to_string(),
+                log.origin.to_string(),
+                log.message,
+            ])?;
+        Ok(())
+    }
+
+    fn write_command(&mut self, timestamp: OffsetDateTime, command: &str) -> Result<()> {
+        self.conn
+            .prepare_cached(
+                "INSERT INTO commands (session_id, timestamp, command) VALUES (?1, ?2, ?3)",
+            )?
+            .execute(params![self.session_id, timestamp, command])?;
+        Ok(())
+    }
+
+    /// Stores the metadata as JSON in the sessions table.
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET metadata = ?1 WHERE id = ?2",
+            params![metadata.to_json()?, self.session_id],
+        )?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.conn.execute_batch("COMMIT; BEGIN")?;
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sessions SET ended_at = ?1 WHERE id = ?2",
+            params![OffsetDateTime::now_utc(), self.session_id],
+        )?;
+        self.conn.execute_batch("COMMIT")?;
+        Ok(())
+    }
+
+    /// Deletes the session from the database, leaving other sessions
+    /// alone.
+    fn discard(self: Box<Self>) -> Result<()> {
+        self.conn.execute_batch("ROLLBACK")?;
+        self.conn.execute_batch(&format!(
+            "BEGIN;
+             DELETE FROM data_points WHERE frame_id IN
+                 (SELECT id FROM frames WHERE session_id = {id});
+             DELETE FROM frames WHERE session_id = {id};
+             DELETE FROM logs WHERE session_id = {id};
+             DELETE FROM commands WHERE session_id = {id};
+             DELETE FROM sessions WHERE id = {id};
+             COMMIT;",
+            id = self.session_id
+        ))?;
+        Ok(())
+    }
+}
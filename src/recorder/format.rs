@@ -0,0 +1,378 @@
+use std::{fs, fs::File, sync::Arc};
+
+use anyhow::{Context, Result};
+use arrow_array::{ArrayRef, Float32Array, RecordBatch, StringArray, TimestampMicrosecondArray};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use csv::Writer;
+use parquet::arrow::ArrowWriter;
+use time::OffsetDateTime;
+
+use crate::{
+    config::Format,
+    log::Log,
+    recorder::{
+        database::Database,
+        mcap::McapWriter,
+        metadata::{remove_sidecar, write_sidecar, Metadata},
+        COMMAND_COLUMN, LOG_COLUMN, TIMESTAMP_FORMAT,
+    },
+    telemetry::{DataPoint, Frame},
+};
+
+/// Number of frames to collect before writing them to a columnar
+/// file in one batch.
+const BATCH_SIZE: usize = 1_000;
+
+/// Writes frames, logs and commands to a session file in a
+/// particular format.
+pub trait FrameWriter: Send {
+    fn write_frame(&mut self, frame: Frame) -> Result<()>;
+
+    /// Formats without a place for logs ignore them.
+    fn write_log(&mut self, _log: &Log) -> Result<()> {
+        Ok(())
+    }
+
+    /// Formats without a place for commands ignore them.
+    fn write_command(&mut self, _timestamp: OffsetDateTime, _command: &str) -> Result<()> {
+        Ok(())
+    }
+
+    /// Makes sure everything written so far is on disk.
+    fn sync(&mut self) -> Result<()>;
+
+    /// Writes out anything buffered and closes the file.
+    fn finish(self: Box<Self>) -> Result<()>;
+
+    /// Stores the session's metadata, either in the session file or
+    /// next to it.
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<()>;
+
+    /// Throws away everything written so far.
+    fn discard(self: Box<Self>) -> Result<()>;
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+            Self::Arrow => "arrows",
+            Self::Mcap => "mcap",
+            Self::Sqlite => "db",
+        }
+    }
+
+    /// Whether the columns have to be known before writing the first
+    /// frame. If so, frames with new data points need a new file.
+    pub fn fixed_columns(&self) -> bool {
+        !matches!(self, Self::Mcap | Self::Sqlite)
+    }
+
+    /// Creates a new session file with the given data point columns.
+    pub fn create(&self, path: &str, columns: &[String]) -> Result<Box<dyn FrameWriter>> {
+        Ok(match self {
+            Self::Csv => Box::new(CsvWriter::new(create_file(path)?, path, columns)?),
+            Self::Parquet => {
+                let schema = schema(columns);
+                let writer = ArrowWriter::try_new(create_file(path)?, schema.clone(), None)?;
+                Box::new(BatchWriter::new(ParquetFile(writer), path, schema, columns))
+            }
+            Self::Arrow => {
+                let schema = schema(columns);
+                let writer = StreamWriter::try_new(create_file(path)?, &schema)?;
+                Box::new(BatchWriter::new(ArrowFile(writer), path, schema, columns))
+            }
+            Self::Mcap => Box::new(McapWriter::create(path)?),
+            Self::Sqlite => Box::new(Database::create(path)?),
+        })
+    }
+}
+
+/// Wide CSV with local timestamps. Logs and commands get their own
+/// rows, with just the timestamp and the log or command column set.
+struct CsvWriter {
+    writer: Writer<File>,
+    /// Handle to the same file as `writer`, for syncing.
+    file: File,
+    path: String,
+    columns: Vec<String>,
+}
+
+impl CsvWriter {
+    fn new(file: File, path: &str, columns: &[String]) -> Result<Self> {
+        let sync_file = file.try_clone()?;
+        let mut writer = Writer::from_writer(file);
+        let mut header = vec!["timestamp", LOG_COLUMN, COMMAND_COLUMN];
+        header.extend(columns.iter().map(|c| c.as_str()));
+        writer.write_record(header)?;
+        Ok(Self {
+            writer,
+            file: sync_file,
+            path: path.to_string(),
+            columns: columns.to_vec(),
+        })
+    }
+}
+
+impl CsvWriter {
+    /// Returns an empty record with the timestamp set.
+    fn record(&self, timestamp: OffsetDateTime) -> Vec<String> {
+        let mut record = vec![timestamp.format(TIMESTAMP_FORMAT).unwrap()];
+        record.resize(self.columns.len() + 3, String::new());
+        record
+    }
+}
+
+impl FrameWriter for CsvWriter {
+    fn write_frame(&mut self, frame: Frame) -> Result<()> {
+        let mut record = self.record(frame.timestamp);
+        for data_point in &frame.data_points {
+            if let Some(index) = self.columns.iter().position(|c| c == &data_point.name) {
+                record[index + 3] = data_point.value.to_string();
+            }
+        }
+        self.writer.write_record(record)?;
+        Ok(())
+    }
+
+    fn write_log(&mut self, log: &Log) -> Result<()> {
+        let mut record = self.record(log.timestamp);
+        record[1] = log.to_string();
+        self.writer.write_record(record)?;
+        Ok(())
+    }
+
+    fn write_command(&mut self, timestamp: OffsetDateTime, command: &str) -> Result<()> {
+        let mut record = self.record(timestamp);
+        record[2] = command.to_string();
+        self.writer.write_record(record)?;
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.sync()
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        write_sidecar(&self.path, metadata)
+    }
+
+    fn discard(self: Box<Self>) -> Result<()> {
+        remove_files(&self.path)
+    }
+}
+
+/// Schema for columnar formats, a UTC timestamp, nullable log and
+/// command columns, and one nullable float column per data point.
+fn schema(columns: &[String]) -> SchemaRef {
+    let mut fields = vec![
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new(LOG_COLUMN, DataType::Utf8, true),
+        Field::new(COMMAND_COLUMN, DataType::Utf8, true),
+    ];
+    fields.extend(
+        columns
+            .iter()
+            .map(|c| Field::new(c, DataType::Float32, true)),
+    );
+    Arc::new(Schema::new(fields))
+}
+
+/// A columnar file which is written to in record batches.
+trait BatchFile: Send {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()>;
+    fn sync(&mut self) -> Result<()>;
+    fn finish(self) -> Result<()>;
+}
+
+/// Apache Parquet file. Only readable once it has been finished.
+struct ParquetFile(ArrowWriter<File>);
+
+impl BatchFile for ParquetFile {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        Ok(self.0.write(batch)?)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        // Closes the current row group.
+        self.0.flush()?;
+        self.0.inner().sync_data()?;
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        let file = self.0.into_inner()?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
+
+/// Apache Arrow IPC stream, which stays readable up to the last
+/// batch written, even if it's never finished.
+struct ArrowFile(StreamWriter<File>);
+
+impl BatchFile for ArrowFile {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        Ok(self.0.write(batch)?)
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.0.flush()?;
+        self.0.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.0.finish()?;
+        self.0.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+/// A row of a columnar file, which is either a frame, a log or a
+/// command.
+#[derive(Default)]
+struct Row {
+    timestamp: i64,
+    data_points: Vec<DataPoint>,
+    log: Option<String>,
+    command: Option<String>,
+}
+
+/// Microseconds since the Unix epoch.
+fn micros(timestamp: OffsetDateTime) -> i64 {
+    (timestamp.unix_timestamp_nanos() / 1_000) as i64
+}
+
+/// Collects rows into record batches for a columnar file.
+struct BatchWriter<F> {
+    file: F,
+    path: String,
+    schema: SchemaRef,
+    columns: Vec<String>,
+    rows: Vec<Row>,
+}
+
+impl<F: BatchFile> BatchWriter<F> {
+    fn new(file: F, path: &str, schema: SchemaRef, columns: &[String]) -> Self {
+        Self {
+            file,
+            path: path.to_string(),
+            schema,
+            columns: columns.to_vec(),
+            rows: Vec::with_capacity(BATCH_SIZE),
+        }
+    }
+
+    fn push(&mut self, row: Row) -> Result<()> {
+        self.rows.push(row);
+        if self.rows.len() >= BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let timestamps =
+            TimestampMicrosecondArray::from_iter_values(self.rows.iter().map(|r| r.timestamp))
+                .with_timezone("UTC");
+        let logs = self
+            .rows
+            .iter()
+            .map(|r| r.log.as_deref())
+            .collect::<StringArray>();
+        let commands = self
+            .rows
+            .iter()
+            .map(|r| r.command.as_deref())
+            .collect::<StringArray>();
+        let mut arrays: Vec<ArrayRef> =
+            vec![Arc::new(timestamps), Arc::new(logs), Arc::new(commands)];
+        for column in &self.columns {
+            let values = self
+                .rows
+                .iter()
+                .map(|r| {
+                    r.data_points
+                        .iter()
+                        .find(|dp| &dp.name == column)
+                        .map(|dp| dp.value)
+                })
+                .collect::<Float32Array>();
+            arrays.push(Arc::new(values));
+        }
+        let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+        self.file.write(&batch)?;
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+impl<F: BatchFile> FrameWriter for BatchWriter<F> {
+    fn write_frame(&mut self, frame: Frame) -> Result<()> {
+        self.push(Row {
+            timestamp: micros(frame.timestamp),
+            data_points: frame.data_points,
+            ..Default::default()
+        })
+    }
+
+    fn write_log(&mut self, log: &Log) -> Result<()> {
+        self.push(Row {
+            timestamp: micros(log.timestamp),
+            log: Some(log.to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn write_command(&mut self, timestamp: OffsetDateTime, command: &str) -> Result<()> {
+        self.push(Row {
+            timestamp: micros(timestamp),
+            command: Some(command.to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.write_batch()?;
+        self.file.sync()
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.write_batch()?;
+        self.file.finish()
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        write_sidecar(&self.path, metadata)
+    }
+
+    fn discard(self: Box<Self>) -> Result<()> {
+        remove_files(&self.path)
+    }
+}
+
+fn create_file(path: &str) -> Result<File> {
+    File::create(path).with_context(|| format!("Failed to open file: {}", path))
+}
+
+/// Deletes a session file and its metadata.
+pub fn remove_files(path: &str) -> Result<()> {
+    fs::remove_file(path).with_context(|| format!("Failed to delete file: {}", path))?;
+    remove_sidecar(path)
+}
@@ -0,0 +1,125 @@
+use std::collections::{HashMap, VecDeque};
+
+use time::{Duration, OffsetDateTime};
+
+use super::Entry;
+use crate::{
+    log::Log,
+    telemetry::{DataPoint, Frame},
+};
+
+/// An entry as kept in the history. Frames only keep their values,
+/// referring to the data point names by index, so that minutes of
+/// telemetry don't take more memory than the values themselves.
+enum Row {
+    Frame {
+        timestamp: OffsetDateTime,
+        values: Vec<(u32, f32)>,
+    },
+    Log(Log),
+    Command(OffsetDateTime, String),
+}
+
+impl Row {
+    fn timestamp(&self) -> OffsetDateTime {
+        match self {
+            Self::Frame { timestamp, .. } => *timestamp,
+            Self::Log(log) => log.timestamp,
+            Self::Command(timestamp, _) => *timestamp,
+        }
+    }
+}
+
+/// Recent entries, for exporting time ranges.
+pub struct History {
+    /// How far back entries are kept.
+    length: Duration,
+    /// Every data point name seen, in the order they were first seen.
+    names: Vec<String>,
+    indices: HashMap<String, u32>,
+    rows: VecDeque<Row>,
+}
+
+impl History {
+    pub fn new(seconds: f32) -> Self {
+        Self {
+            length: Duration::seconds_f32(seconds),
+            names: Vec::new(),
+            indices: HashMap::new(),
+            rows: VecDeque::new(),
+        }
+    }
+
+    /// Keeps the entry, dropping entries which have fallen out of the
+    /// history.
+    pub fn push(&mut self, entry: &Entry) {
+        let timestamp = entry.timestamp();
+        while let Some(oldest) = self.rows.front() {
+            if timestamp - oldest.timestamp() > self.length {
+                self.rows.pop_front();
+            } else {
+                break;
+            }
+        }
+        let row = match entry {
+            Entry::Frame(frame) => Row::Frame {
+                timestamp,
+                values: frame
+                    .data_points
+                    .iter()
+                    .map(|dp| (self.intern(&dp.name), dp.value))
+                    .collect(),
+            },
+            Entry::Log(log) => Row::Log(log.clone()),
+            Entry::Command(timestamp, command) => Row::Command(*timestamp, command.clone()),
+        };
+        self.rows.push_back(row);
+    }
+
+    /// The entries within the time range, along with the names of the
+    /// data points in them.
+    pub fn range(&self, start: OffsetDateTime, end: OffsetDateTime) -> (Vec<Entry>, Vec<String>) {
+        let mut used = vec![false; self.names.len()];
+        let entries = self
+            .rows
+            .iter()
+            .filter(|r| (start..=end).contains(&r.timestamp()))
+            .map(|row| match row {
+                Row::Frame { timestamp, values } => Entry::Frame(Frame {
+                    timestamp: *timestamp,
+                    data_points: values
+                        .iter()
+                        .map(|&(index, value)| {
+                            used[index as usize] = true;
+                            DataPoint::new(&self.names[index as usize], *timestamp, value)
+                        })
+                        .collect(),
+                }),
+                Row::Log(log) => Entry::Log(log.clone()),
+                Row::Command(timestamp, command) => Entry::Command(*timestamp, command.clone()),
+            })
+            .collect();
+        let headers = self
+            .names
+            .iter()
+            .zip(used)
+            .filter(|(_, used)| *used)
+            .map(|(name, _)| name.clone())
+            .collect();
+        (entries, headers)
+    }
+
+    pub fn clear(&mut self) {
+        self.rows.clear();
+    }
+
+    fn intern(&mut self, name: &str) -> u32 {
+        if let Some(index) = self.indices.get(name) {
+            return *index;
+        }
+        let index = self.names.len() as u32;
+        self.names.push(name.to_string());
+        self.indices.insert(name.to_string(), index);
+        index
+    }
+}
@@ -0,0 +1,214 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use time::OffsetDateTime;
+
+use crate::{
+    log::{Log, Severity},
+    recorder::{
+        format::{remove_files, FrameWriter},
+        metadata::{write_sidecar, Metadata},
+    },
+    telemetry::Frame,
+};
+
+/// Marks the start and end of an MCAP file.
+const MAGIC: &[u8; 8] = b"\x89MCAP0\r\n";
+
+/// Record opcodes, see https://mcap.dev/spec.
+const OP_HEADER: u8 = 0x01;
+const OP_FOOTER: u8 = 0x02;
+const OP_SCHEMA: u8 = 0x03;
+const OP_CHANNEL: u8 = 0x04;
+const OP_MESSAGE: u8 = 0x05;
+const OP_DATA_END: u8 = 0x0f;
+
+const TELEMETRY_CHANNEL: u16 = 1;
+const LOG_CHANNEL: u16 = 2;
+const COMMAND_CHANNEL: u16 = 3;
+
+/// Log level in Foxglove's log schema.
+fn log_level(severity: Severity) -> u8 {
+    match severity {
+        Severity::Debug => 1,
+        Severity::Info => 2,
+        Severity::Warning => 3,
+        Severity::Error => 4,
+    }
+}
+
+/// A session recorded as an MCAP file with JSON messages, which opens
+/// directly in Foxglove Studio.
+///
+/// Telemetry, logs and commands each get their own channel. The file
+/// is written without an index, which Foxglove handles fine.
+pub struct McapWriter {
+    file: BufWriter<File>,
+    path: String,
+    /// Next sequence number per channel.
+    sequences: [u32; 3],
+}
+
+impl McapWriter {
+    pub fn create(path: &str) -> Result<Self> {
+        let file = File::create(path).with_context(|| format!("Failed to open file: {}", path))?;
+        let mut writer = Self {
+            file: BufWriter::new(file),
+            path: path.to_string(),
+            sequences: [0; 3],
+        };
+        writer.file.write_all(MAGIC)?;
+
+        let mut header = vec![];
+        put_str(&mut header, "");
+        put_str(&mut header, "mission-control");
+        writer.write_record(OP_HEADER, &header)?;
+
+        // Any data point may show up at any time, so don't list them.
+        let telemetry = json!({
+            "type": "object",
+            "additionalProperties": { "type": "number" },
+        });
+        let log = json!({
+            "type": "object",
+            "properties": {
+                "timestamp": {
+                    "type": "object",
+                    "properties": {
+                        "sec": { "type": "integer" },
+                        "nsec": { "type": "integer" },
+                    },
+                },
+                "level": { "type": "integer" },
+                "message": { "type": "string" },
+                "name": { "type": "string" },
+                "file": { "type": "string" },
+                "line": { "type": "integer" },
+            },
+        });
+        let command = json!({
+            "type": "object",
+            "properties": { "command": { "type": "string" } },
+        });
+        for (id, schema, topic, definition) in [
+            (TELEMETRY_CHANNEL, "mctl.Telemetry", "/telemetry", telemetry),
+            (LOG_CHANNEL, "foxglove.Log", "/log", log),
+            (COMMAND_CHANNEL, "mctl.Command", "/command", command),
+        ] {
+            writer.write_channel(id, schema, topic, &definition)?;
+        }
+        Ok(writer)
+    }
+
+    /// Writes a schema and a channel using it, with the same ID.
+    fn write_channel(
+        &mut self,
+        id: u16,
+        schema: &str,
+        topic: &str,
+        definition: &Value,
+    ) -> Result<()> {
+        let mut record = vec![];
+        record.extend_from_slice(&id.to_le_bytes());
+        put_str(&mut record, schema);
+        put_str(&mut record, "jsonschema");
+        put_str(&mut record, &definition.to_string());
+        self.write_record(OP_SCHEMA, &record)?;
+
+        let mut record = vec![];
+        record.extend_from_slice(&id.to_le_bytes());
+        record.extend_from_slice(&id.to_le_bytes());
+        put_str(&mut record, topic);
+        put_str(&mut record, "json");
+        // No metadata.
+        record.extend_from_slice(&0u32.to_le_bytes());
+        self.write_record(OP_CHANNEL, &record)
+    }
+
+    fn write_message(&mut self, channel: u16, time: OffsetDateTime, data: &Value) -> Result<()> {
+        let sequence = &mut self.sequences[channel as usize - 1];
+        let mut record = vec![];
+        record.extend_from_slice(&channel.to_le_bytes());
+        record.extend_from_slice(&sequence.to_le_bytes());
+        *sequence += 1;
+        let time = time.unix_timestamp_nanos() as u64;
+        // Log and publish time are the same.
+        record.extend_from_slice(&time.to_le_bytes());
+        record.extend_from_slice(&time.to_le_bytes());
+        record.extend_from_slice(data.to_string().as_bytes());
+        self.write_record(OP_MESSAGE, &record)
+    }
+
+    fn write_record(&mut self, opcode: u8, record: &[u8]) -> Result<()> {
+        self.file.write_all(&[opcode])?;
+        self.file.write_all(&(record.len() as u64).to_le_bytes())?;
+        self.file.write_all(record)?;
+        Ok(())
+    }
+}
+
+impl FrameWriter for McapWriter {
+    fn write_frame(&mut self, frame: Frame) -> Result<()> {
+        let data: Map<String, Value> = frame
+            .data_points
+            .into_iter()
+            .map(|dp| (dp.name, json!(dp.value)))
+            .collect();
+        self.write_message(TELEMETRY_CHANNEL, frame.timestamp, &Value::Object(data))
+    }
+
+    fn write_log(&mut self, log: &Log) -> Result<()> {
+        let data = json!({
+            "timestamp": {
+                "sec": log.timestamp.unix_timestamp(),
+                "nsec": log.timestamp.nanosecond(),
+            },
+            "level": log_level(log.severity),
+            "message": log.message,
+            "name": log.origin.to_string(),
+            "file": "",
+            "line": 0,
+        });
+        self.write_message(LOG_CHANNEL, log.timestamp, &data)
+    }
+
+    fn write_command(&mut self, timestamp: OffsetDateTime, command: &str) -> Result<()> {
+        self.write_message(COMMAND_CHANNEL, timestamp, &json!({ "command": command }))
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        // No CRC for the data section.
+        self.write_record(OP_DATA_END, &0u32.to_le_bytes())?;
+        // No summary section either.
+        self.write_record(OP_FOOTER, &[0; 20])?;
+        self.file.write_all(MAGIC)?;
+        self.file.flush()?;
+        self.file.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    fn write_metadata(&mut self, metadata: &Metadata) -> Result<()> {
+        write_sidecar(&self.path, metadata)
+    }
+
+    fn discard(self: Box<Self>) -> Result<()> {
+        drop(self.file);
+        remove_files(&self.path)
+    }
+}
+
+/// Appends a length-prefixed string.
+fn put_str(record: &mut Vec<u8>, s: &str) {
+    record.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    record.extend_from_slice(s.as_bytes());
+}
@@ -0,0 +1,76 @@
+use std::{fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::config::Config;
+
+/// Everything needed to make sense of a session later on.
+#[derive(Clone, Debug, Serialize)]
+pub struct Metadata {
+    pub start: String,
+    /// Only set once the session has been closed.
+    pub end: Option<String>,
+    pub version: String,
+    pub vehicle: String,
+    pub notes: String,
+    /// The effective configuration the session was recorded with.
+    pub config: Config,
+}
+
+impl Metadata {
+    pub fn new(config: &Config, notes: &str) -> Self {
+        Self {
+            start: timestamp(),
+            end: None,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            vehicle: config.vehicle.clone(),
+            notes: notes.to_string(),
+            config: config.clone(),
+        }
+    }
+
+    /// Sets the end time, unless it's already been set.
+    pub fn close(&mut self) {
+        self.end.get_or_insert_with(timestamp);
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn timestamp() -> String {
+    format_time(OffsetDateTime::now_local().unwrap())
+}
+
+pub fn format_time(time: OffsetDateTime) -> String {
+    time.format(&Rfc3339).unwrap()
+}
+
+/// Path of the metadata file next to a session file. Keeps the
+/// session's extension, so that sessions in different formats with
+/// the same name don't share one.
+fn sidecar_path(path: &str) -> PathBuf {
+    PathBuf::from(format!("{path}.json"))
+}
+
+/// Writes the metadata to a JSON file next to the session file.
+pub fn write_sidecar(path: &str, metadata: &Metadata) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    fs::write(&sidecar, metadata.to_json()?)
+        .with_context(|| format!("Failed to write file: {}", sidecar.display()))
+}
+
+/// Deletes the metadata file next to the session file, if there is
+/// one.
+pub fn remove_sidecar(path: &str) -> Result<()> {
+    let sidecar = sidecar_path(path);
+    match fs::remove_file(&sidecar) {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("Failed to delete file: {}", sidecar.display()))
+        }
+        _ => Ok(()),
+    }
+}
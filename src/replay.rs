@@ -0,0 +1,163 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use csv::Reader;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tokio::{
+    io::{duplex, AsyncWriteExt},
+    task,
+    time::{sleep_until, Duration, Instant},
+};
+
+use crate::{
+    bus::{Bus, Subscriber},
+    capture::read_capture,
+    log::{Log, Origin},
+    recorder::{COMMAND_COLUMN, LOG_COLUMN, TIMESTAMP_FORMAT},
+    serial::read_packages,
+    source::{CommandSent, CommandSink, LinkStats, LinkStatus, TelemetrySource},
+    telemetry::Frame,
+    Command, Message,
+};
+
+/// Plays back a session previously exported by the recorder, or a
+/// raw capture, as if it was coming from a vehicle.
+#[derive(Clone, Debug)]
+pub struct Replay {
+    path: PathBuf,
+    raw: bool,
+    speed: f64,
+}
+
+impl Replay {
+    pub fn new(path: &Path, raw: bool, speed: f64) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            raw,
+            speed,
+        }
+    }
+
+    /// Returns the instant a recorded offset should be played back
+    /// at, adjusted for playback speed.
+    fn playback_time(&self, start: Instant, offset: f64) -> Instant {
+        start + Duration::from_secs_f64(offset.max(0.) / self.speed)
+    }
+
+    async fn replay_csv(&self, message_bus: &Bus) -> Result<()> {
+        let mut rdr = Reader::from_path(&self.path)
+            .with_context(|| format!("Failed to open file: {}", self.path.display()))?;
+        let headers = rdr.headers()?.clone();
+
+        message_bus.send(Message::Log(Log::info(
+            Origin::System,
+            format!("Replaying {} at {}x speed", self.path.display(), self.speed),
+        )));
+
+        let start = Instant::now();
+        let mut first_timestamp = None;
+        for record in rdr.records() {
+            let record = record?;
+            let timestamp = PrimitiveDateTime::parse(&record[0], TIMESTAMP_FORMAT)
+                .with_context(|| format!("Invalid timestamp: {}", &record[0]))?;
+            let offset = timestamp - *first_timestamp.get_or_insert(timestamp);
+            sleep_until(self.playback_time(start, offset.as_seconds_f64())).await;
+
+            let mut data = vec![];
+            for (name, value) in headers.iter().zip(record.iter()).skip(1) {
+                match name {
+                    _ if value.is_empty() => {}
+                    LOG_COLUMN => message_bus.send(Message::Log(Log::parse(
+                        OffsetDateTime::now_local().unwrap(),
+                        value,
+                    ))),
+                    COMMAND_COLUMN => {
+                        message_bus.send(Message::CommandSent(CommandSent::new(value, Ok(()))))
+                    }
+                    _ => data.push(
+                        value
+                            .parse::<f32>()
+                            .map(|v| (name.to_string(), v))
+                            .with_context(|| format!("Invalid value for {name}: {value}"))?,
+                    ),
+                }
+            }
+            if data.is_empty() {
+                // Just a log or command.
+                continue;
+            }
+
+            // Graphs plot relative to the current time, so pretend
+            // the frame has just arrived.
+            let frame = Frame::new(OffsetDateTime::now_local().unwrap(), &data);
+            message_bus.send(Message::Telemetry(frame));
+        }
+
+        Ok(())
+    }
+
+    /// Feeds the bytes of a raw capture through the same decoder
+    /// used for live links.
+    async fn replay_raw(&self, message_bus: &Bus) -> Result<()> {
+        let chunks = read_capture(&self.path)?;
+
+        message_bus.send(Message::Log(Log::info(
+            Origin::System,
+            format!(
+                "Replaying raw capture {} at {}x speed",
+                self.path.display(),
+                self.speed
+            ),
+        )));
+
+        let (mut reader, mut writer) = duplex(4096);
+        let start = Instant::now();
+        let replay = self.clone();
+        task::spawn(async move {
+            for (offset, bytes) in chunks {
+                sleep_until(replay.playback_time(start, offset.as_secs_f64())).await;
+                if writer.write_all(&bytes).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        read_packages(&mut reader, None, &mut LinkStats::default(), message_bus).await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TelemetrySource for Replay {
+    async fn listen(&mut self, message_bus: Bus) -> Result<()> {
+        let link = format!("replay of {}", self.path.display());
+        message_bus.send(Message::Link(LinkStatus::Connected { link: link.clone() }));
+        if self.raw {
+            self.replay_raw(&message_bus).await?;
+        } else {
+            self.replay_csv(&message_bus).await?;
+        }
+        message_bus.send(Message::Link(LinkStatus::Disconnected {
+            link,
+            reason: "replay finished".to_string(),
+            retries: 0,
+        }));
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CommandSink for Replay {
+    async fn send_commands(&mut self, mut rx: Subscriber, tx: Bus) -> Result<()> {
+        while let Some(msg) = rx.recv().await {
+            if let Message::Command(Command::SendCommand(cmd)) = msg {
+                tx.send(Message::Log(Log::warning(
+                    Origin::System,
+                    format!("Not sending command during replay: {cmd}"),
+                )));
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,136 +1,280 @@
-use std::time::Duration;
+use std::{future::pending, sync::Arc, time::Duration};
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
 use postcard::take_from_bytes_cobs;
 use postcard_telemetry::transport;
 use time::OffsetDateTime;
 use tokio::{
-    io::{AsyncRead, AsyncReadExt},
-    sync::broadcast::{Receiver, Sender},
-    time::sleep,
+    io::{split, AsyncRead, AsyncReadExt, WriteHalf},
+    select,
+    sync::Mutex,
+    time::{interval, sleep},
 };
-use tokio_serial::SerialPortBuilderExt;
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
 
-use crate::{telemetry::Frame, Command, Message};
+use crate::{
+    bus::{Bus, Subscriber},
+    capture::Capture,
+    log::{Log, Origin},
+    recorder::FileTemplate,
+    source::{
+        forward_commands, CommandSink, LinkStats, LinkStatus, TelemetrySource, STATS_INTERVAL,
+    },
+    telemetry::Frame,
+    Command, Message,
+};
 
-pub async fn send_commands(
-    path: &str,
+/// Manages a serial port, used both for receiving telemetry and
+/// sending commands.
+///
+/// The port is opened once and split into reader and writer halves.
+/// Clones share the same writer, so commands go out over the port
+/// the listener currently has open.
+#[derive(Clone, Debug)]
+pub struct SerialLink {
+    path: String,
     baud_rate: u32,
-    mut rx: Receiver<Message>,
-    tx: Sender<Message>,
-) -> Result<()> {
-    loop {
-        match tokio_serial::new(path, baud_rate).open() {
-            Ok(mut tty) => {
-                if let Ok(Message::Command(Command::SendCommand(cmd))) = rx.recv().await {
-                    let _ = match tty.write_all(cmd.as_bytes()) {
-                        Ok(_) => tx.send(Message::Log(format!("[SYSTEM] Sent command: {cmd}"))),
-                        Err(e) => tx.send(Message::Log(format!(
-                            "[SYSTEM] Failed to send command: {cmd} due to {e}"
-                        ))),
-                    };
+    /// Where to write raw captures to, if enabled.
+    capture: Option<FileTemplate>,
+    writer: Arc<Mutex<Option<WriteHalf<SerialStream>>>>,
+}
+
+impl SerialLink {
+    pub fn new(path: &str, baud_rate: u32, capture: Option<FileTemplate>) -> Self {
+        Self {
+            path: path.to_string(),
+            baud_rate,
+            capture,
+            writer: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn name(&self) -> String {
+        format!("{} @ {} baud", self.path, self.baud_rate)
+    }
+}
+
+#[async_trait]
+impl TelemetrySource for SerialLink {
+    async fn listen(&mut self, message_bus: Bus) -> Result<()> {
+        let mut capture = if let Some(files) = &self.capture {
+            match Capture::create(files) {
+                Ok((capture, capture_path)) => {
+                    message_bus.send(Message::Log(Log::info(
+                        Origin::System,
+                        format!("Capturing raw bytes to {capture_path}"),
+                    )));
+                    Some(capture)
+                }
+                Err(e) => {
+                    message_bus.send(Message::Log(Log::warning(
+                        Origin::System,
+                        format!("Failed to start raw capture: {e}"),
+                    )));
+                    None
                 }
             }
-            _ => {
-                sleep(Duration::from_secs(1)).await;
+        } else {
+            None
+        };
+
+        let mut stats = LinkStats::default();
+        let mut retries = 0;
+        let mut rx = message_bus.subscribe();
+        loop {
+            match tokio_serial::new(&self.path, self.baud_rate).open_native_async() {
+                Ok(tty) => {
+                    let (mut reader, writer) = split(tty);
+                    *self.writer.lock().await = Some(writer);
+                    retries = 0;
+                    message_bus.send(Message::Link(LinkStatus::Connected { link: self.name() }));
+
+                    let read =
+                        read_packages(&mut reader, capture.as_mut(), &mut stats, &message_bus);
+                    let new_port = select! {
+                        _ = read => None,
+                        port = next_port(&mut rx) => Some(port),
+                    };
+
+                    *self.writer.lock().await = None;
+                    message_bus.send(Message::Link(LinkStatus::Disconnected {
+                        link: self.name(),
+                        reason: if new_port.is_some() {
+                            "switching port".to_string()
+                        } else {
+                            "TTY closed".to_string()
+                        },
+                        retries,
+                    }));
+                    if let Some((path, baud_rate)) = new_port {
+                        self.path = path;
+                        self.baud_rate = baud_rate;
+                    }
+                }
+                Err(e) => {
+                    retries += 1;
+                    message_bus.send(Message::Link(LinkStatus::Disconnected {
+                        link: self.name(),
+                        reason: e.to_string(),
+                        retries,
+                    }));
+                    select! {
+                        _ = sleep(Duration::from_secs(1)) => {}
+                        (path, baud_rate) = next_port(&mut rx) => {
+                            self.path = path;
+                            self.baud_rate = baud_rate;
+                            retries = 0;
+                        }
+                    }
+                }
             }
         }
     }
 }
 
-pub async fn listen(path: &str, baud_rate: u32, message_bus: Sender<Message>) -> Result<()> {
+/// Waits for the operator to select a different serial port.
+async fn next_port(rx: &mut Subscriber) -> (String, u32) {
+    while let Some(msg) = rx.recv().await {
+        if let Message::Command(Command::SelectPort { path, baud }) = msg {
+            return (path, baud);
+        }
+    }
+    pending().await
+}
+
+/// Lists the names of all serial ports on the system.
+pub fn available_ports() -> Vec<String> {
+    tokio_serial::available_ports()
+        .map(|ports| ports.into_iter().map(|p| p.port_name).collect())
+        .unwrap_or_default()
+}
+
+#[async_trait]
+impl CommandSink for SerialLink {
+    async fn send_commands(&mut self, rx: Subscriber, tx: Bus) -> Result<()> {
+        forward_commands(&self.writer, rx, tx).await
+    }
+}
+
+/// Reads packages from a byte stream and publishes them onto the
+/// message bus until the stream is closed. Link statistics are
+/// published periodically.
+pub(crate) async fn read_packages(
+    reader: &mut (impl AsyncRead + std::marker::Unpin),
+    mut capture: Option<&mut Capture>,
+    stats: &mut LinkStats,
+    message_bus: &Bus,
+) {
+    let mut message_bytes: Vec<u8> = vec![];
+    let mut stats_interval = interval(STATS_INTERVAL);
     loop {
-        match tokio_serial::new(path, baud_rate).open_native_async() {
-            Ok(mut tty) => {
-                let mut message_bytes: Vec<u8> = vec![];
-                loop {
-                    match read_serial(&mut tty, &mut message_bytes).await {
-                        Ok(Some(transport::Package::Telemetry(frame))) => {
-                            let internal_frame = Frame::new(
-                                OffsetDateTime::now_local().unwrap(),
-                                &frame
-                                    .into_iter()
-                                    .map(|(s, v)| match v {
-                                        transport::telemetry::DataPoint::F32(n) => (s, n),
-                                        transport::telemetry::DataPoint::U32(n) => (s, n as f32),
-                                        transport::telemetry::DataPoint::I32(n) => (s, n as f32),
-                                    })
-                                    .collect::<Vec<_>>(),
-                            );
-                            if message_bus
-                                .send(Message::Telemetry(internal_frame))
-                                .is_err()
-                            {
-                                println!("[WARN] Message bus saturated, losing data")
-                            }
-                        }
-                        Ok(Some(transport::Package::Log(log))) => {
-                            if message_bus.send(Message::Log(format!("{log}"))).is_err() {
-                                println!("[WARN] Message bus saturated, losing data")
-                            }
-                        }
-                        Ok(None) => {
-                            // TTY might be closed, return to outer loop.
-                            break;
-                        }
-                        Err(e) => {
-                            if message_bus
-                                .send(Message::Log(format!("[WARN] {e}")))
-                                .is_err()
-                            {
-                                println!("[WARN] Message bus saturated, losing data")
-                            }
-                        }
+        // Reading is cancel safe, as partial packages are kept in
+        // `message_bytes`.
+        select! {
+            result = read_serial(reader, &mut message_bytes, capture.as_deref_mut(), stats) => {
+                match result {
+                    Ok(Some(package)) => {
+                        stats.packets += 1;
+                        publish_package(package, message_bus);
+                    }
+                    Ok(None) => {
+                        // Stream might be closed, return to caller.
+                        break;
+                    }
+                    Err(e) => {
+                        message_bus.send(Message::Log(Log::warning(Origin::Link, e.to_string())));
                     }
                 }
             }
-            Err(e) => {
-                let _ = message_bus.send(Message::Log(format!(
-                    "[WARN] TTY disconnected, waiting for connection... ({e})"
-                )));
-                sleep(Duration::from_secs(1)).await;
+            _ = stats_interval.tick() => {
+                message_bus.send(Message::LinkStats(stats.clone()));
             }
         }
     }
 }
 
-/// Reads from the serial port and tries to parse a COBS-encoded frame.
-async fn read_serial(
+/// Converts a package received from the vehicle into a message and
+/// publishes it onto the message bus.
+pub(crate) fn publish_package(package: transport::Package, message_bus: &Bus) {
+    let msg = match package {
+        transport::Package::Telemetry(frame) => Message::Telemetry(Frame::new(
+            OffsetDateTime::now_local().unwrap(),
+            &frame
+                .into_iter()
+                .map(|(s, v)| match v {
+                    transport::telemetry::DataPoint::F32(n) => (s, n),
+                    transport::telemetry::DataPoint::U32(n) => (s, n as f32),
+                    transport::telemetry::DataPoint::I32(n) => (s, n as f32),
+                })
+                .collect::<Vec<_>>(),
+        )),
+        transport::Package::Log(log) => Message::Log(Log::firmware(&log.to_string())),
+    };
+    message_bus.send(msg);
+}
+
+/// Largest package we expect, if the buffer grows beyond this
+/// without an end of package marker it is discarded.
+const MAX_PACKAGE_SIZE: usize = 16 * 1024;
+
+/// Reads from the serial port until it can parse a COBS-encoded
+/// frame. Works on any byte stream, not just TTYs. Returns `None` if
+/// the stream has been closed. Everything read is also written to
+/// the capture, if there is one, and counted in the stats.
+pub(crate) async fn read_serial(
     tty: &mut (impl AsyncRead + std::marker::Unpin),
     message_bytes: &mut Vec<u8>,
+    mut capture: Option<&mut Capture>,
+    stats: &mut LinkStats,
 ) -> Result<Option<transport::Package>> {
     let mut buf = [0u8; 2048];
 
-    if let Ok(n) = tty.read(&mut buf).await {
-        message_bytes.extend_from_slice(&buf[..n]);
+    loop {
+        // A single read can contain several packages, so only read
+        // more once there is no complete one left in the buffer.
+        let Some(end) = message_bytes.iter().position(|&b| b == 0) else {
+            let n = match tty.read(&mut buf).await {
+                Ok(0) | Err(_) => return Ok(None),
+                Ok(n) => n,
+            };
+            message_bytes.extend_from_slice(&buf[..n]);
+            stats.bytes += n as u64;
+            if let Some(capture) = capture.as_deref_mut() {
+                capture
+                    .write(&buf[..n])
+                    .context("Failed to write raw capture")?;
+            }
+            if !message_bytes.contains(&0) && message_bytes.len() > MAX_PACKAGE_SIZE {
+                // No end of package in sight, we must have missed it
+                // somehow.
+                stats.overflows += 1;
+                stats.reject(message_bytes);
+                message_bytes.clear();
+            }
+            continue;
+        };
+        // Decoding happens in place, so keep a copy of the
+        // package's raw bytes in case we need to reject it.
+        let raw_package = message_bytes[..=end].to_vec();
         match take_from_bytes_cobs::<transport::Package>(message_bytes) {
             Ok((package, rest)) => {
                 *message_bytes = rest.to_vec();
                 return Ok(Some(package));
             }
             Err(postcard::Error::DeserializeBadEncoding) => {
-                // This can happen if we have some bytes but not
-                // enough to assemble a full message, especially a
-                // large one. In that case we should just continue
-                // reading. If we encounter a zero byte though, the
-                // message should have ended, so we discard the junk.
-                if let Some(idx) = message_bytes.iter().position(|&b| b == 0) {
-                    println!(
-                        "[WARN] Got bad encoding: {}",
-                        message_bytes
-                            .iter()
-                            .take(idx)
-                            .map(|b| format!("{b:x?}"))
-                            .collect::<String>()
-                    );
-                    message_bytes.drain(..idx);
-                }
+                // The package has ended, but it isn't valid COBS, so
+                // discard the junk.
+                stats.bad_encodings += 1;
+                stats.reject(&raw_package[..end]);
+                message_bytes.drain(..end);
             }
-            Err(postcard::Error::DeserializeUnexpectedEnd) => {
+            Err(postcard::Error::DeserializeUnexpectedEnd) if end == 0 => {
                 // We hit these because zero bytes are the
                 // end-of-package markers in COBS, but simulated
                 // serial devices spit out zeroes if there's no actual
                 // data. Just skip over the zeroes.
+                stats.unexpected_ends += 1;
                 match message_bytes.iter().position(|&b| b != 0) {
                     Some(idx) => {
                         message_bytes.drain(..idx);
@@ -140,10 +284,49 @@ async fn read_serial(
                     }
                 }
             }
+            Err(postcard::Error::DeserializeUnexpectedEnd) => {
+                // A package too short to be anything, e.g. line noise
+                // followed by a zero. Skip past it to not get stuck.
+                stats.bad_encodings += 1;
+                stats.reject(&raw_package);
+                message_bytes.drain(..=end);
+            }
             Err(e) => {
+                // The package was framed correctly but did not
+                // deserialize, so skip past it to not get stuck.
+                match e {
+                    postcard::Error::DeserializeBadEnum => stats.unknown_packages += 1,
+                    _ => stats.bad_encodings += 1,
+                }
+                stats.reject(&raw_package);
+                message_bytes.drain(..=end);
                 return Err(anyhow!("Got an error reading serial device: {e}"));
             }
         }
     }
-    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn skips_packages_too_short_to_decode() {
+        let package = transport::Package::Log(transport::Log("hello".to_string()));
+        let mut bytes = vec![0x01, 0x00];
+        bytes.extend(postcard::to_stdvec_cobs(&package).unwrap());
+        let mut reader = &bytes[..];
+        let mut message_bytes = vec![];
+        let mut stats = LinkStats::default();
+
+        let package = read_serial(&mut reader, &mut message_bytes, None, &mut stats)
+            .await
+            .unwrap();
+
+        assert!(
+            matches!(package, Some(transport::Package::Log(log)) if log.to_string() == "hello")
+        );
+        assert_eq!(stats.bad_encodings, 1);
+        assert_eq!(stats.rejected, [vec![0x01, 0x00]]);
+    }
 }
@@ -0,0 +1,211 @@
+use std::{collections::VecDeque, fmt, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use time::OffsetDateTime;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::Mutex,
+};
+
+use crate::{
+    bus::{Bus, Subscriber},
+    config::{self, Config},
+    log::{Log, Origin},
+    recorder::FileTemplate,
+    serial::SerialLink,
+    tcp::TcpLink,
+    udp::UdpLink,
+    Command, Message,
+};
+
+/// Connection state of a link, published onto the message bus
+/// whenever it changes.
+#[derive(Clone, Debug)]
+pub enum LinkStatus {
+    Connected {
+        link: String,
+    },
+    /// Not connected, `retries` counts failed attempts to reconnect
+    /// since the link was last up.
+    Disconnected {
+        link: String,
+        reason: String,
+        retries: u32,
+    },
+}
+
+impl LinkStatus {
+    /// Describes the status change as a log message.
+    pub fn to_log(&self) -> Log {
+        match self {
+            Self::Connected { .. } => Log::info(Origin::Link, self.to_string()),
+            Self::Disconnected { .. } => Log::warning(Origin::Link, self.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for LinkStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connected { link } => write!(f, "Connected to {link}"),
+            Self::Disconnected {
+                link,
+                reason,
+                retries,
+            } => write!(f, "Disconnected from {link} ({reason}, {retries} retries)"),
+        }
+    }
+}
+
+/// Outcome of uplinking a command, published onto the message bus by
+/// command sinks.
+#[derive(Clone, Debug)]
+pub struct CommandSent {
+    pub timestamp: OffsetDateTime,
+    pub command: String,
+    /// Why the command couldn't be sent, if it wasn't.
+    pub result: Result<(), String>,
+}
+
+impl CommandSent {
+    pub fn new(command: &str, result: Result<(), String>) -> Self {
+        Self {
+            timestamp: OffsetDateTime::now_local().unwrap(),
+            command: command.to_string(),
+            result,
+        }
+    }
+
+    /// Describes the outcome as a log message.
+    pub fn to_log(&self) -> Log {
+        let log = match &self.result {
+            Ok(()) => Log::info(Origin::System, format!("Sent command: {}", self.command)),
+            Err(e) => Log::error(
+                Origin::System,
+                format!("Failed to send command: {} due to {e}", self.command),
+            ),
+        };
+        Log {
+            timestamp: self.timestamp,
+            ..log
+        }
+    }
+}
+
+/// Running totals of traffic on a link, published onto the message
+/// bus every [`STATS_INTERVAL`] while connected.
+#[derive(Clone, Debug, Default)]
+pub struct LinkStats {
+    /// Bytes received
+    pub bytes: u64,
+
+    /// Packages successfully decoded
+    pub packets: u64,
+
+    /// Packages discarded because they were not valid COBS or
+    /// postcard
+    pub bad_encodings: u64,
+
+    /// Stray end of package markers skipped
+    pub unexpected_ends: u64,
+
+    /// Packages of a type we don't know about
+    pub unknown_packages: u64,
+
+    /// Times the receive buffer grew too large without finding the
+    /// end of a package
+    pub overflows: u64,
+
+    /// The last [`MAX_REJECTED`] byte sequences discarded, oldest
+    /// first
+    pub rejected: VecDeque<Vec<u8>>,
+}
+
+impl LinkStats {
+    /// Total number of packages that were lost to decoding errors.
+    pub fn decode_errors(&self) -> u64 {
+        self.bad_encodings + self.unknown_packages + self.overflows
+    }
+
+    /// Keeps a discarded byte sequence around for inspection.
+    pub fn reject(&mut self, bytes: &[u8]) {
+        if self.rejected.len() >= MAX_REJECTED {
+            self.rejected.pop_front();
+        }
+        self.rejected.push_back(bytes.to_vec());
+    }
+}
+
+/// Number of rejected byte sequences to keep in [`LinkStats`].
+pub const MAX_REJECTED: usize = 32;
+
+/// How often links publish their [`LinkStats`].
+pub const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Something that produces telemetry, e.g. a serial port or a
+/// simulator.
+#[async_trait]
+pub trait TelemetrySource: Send {
+    /// Reads from the link and publishes everything received onto
+    /// the message bus. Should only return on unrecoverable errors or
+    /// once the source is exhausted.
+    async fn listen(&mut self, message_bus: Bus) -> Result<()>;
+}
+
+/// Something that accepts commands to uplink to the vehicle.
+#[async_trait]
+pub trait CommandSink: Send {
+    /// Waits for [`crate::Command::SendCommand`] on the message bus
+    /// and forwards them over the link. Should only return on
+    /// unrecoverable errors or once the message bus has closed.
+    async fn send_commands(&mut self, rx: Subscriber, tx: Bus) -> Result<()>;
+}
+
+/// Builds the telemetry source and command sink described by the
+/// config.
+pub fn open(cfg: &Config) -> (Box<dyn TelemetrySource>, Box<dyn CommandSink>) {
+    match &cfg.source {
+        config::Source::Serial(serial) => split(SerialLink::new(
+            &serial.path,
+            serial.baud,
+            serial.capture.then(|| FileTemplate::new(cfg)),
+        )),
+        config::Source::Tcp(tcp) => split(TcpLink::new(&tcp.address)),
+        config::Source::Udp(udp) => split(UdpLink::new(&udp.bind, udp.peer.as_deref())),
+    }
+}
+
+/// Uses a link as both telemetry source and command sink.
+pub fn split<L>(link: L) -> (Box<dyn TelemetrySource>, Box<dyn CommandSink>)
+where
+    L: TelemetrySource + CommandSink + Clone + 'static,
+{
+    (Box::new(link.clone()), Box::new(link))
+}
+
+/// Writes commands from the message bus to whichever writer is
+/// currently connected, for links that share one connection between
+/// source and sink.
+pub async fn forward_commands<W>(
+    writer: &Mutex<Option<W>>,
+    mut rx: Subscriber,
+    tx: Bus,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    while let Some(msg) = rx.recv().await {
+        if let Message::Command(Command::SendCommand(cmd)) = msg {
+            let result = match writer.lock().await.as_mut() {
+                Some(writer) => writer
+                    .write_all(cmd.as_bytes())
+                    .await
+                    .map_err(|e| e.to_string()),
+                None => Err("not connected".to_string()),
+            };
+            tx.send(Message::CommandSent(CommandSent::new(&cmd, result)));
+        }
+    }
+    Ok(())
+}
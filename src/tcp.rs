@@ -0,0 +1,84 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tokio::{
+    net::{tcp::OwnedWriteHalf, TcpStream},
+    sync::Mutex,
+    time::sleep,
+};
+
+use crate::{
+    bus::{Bus, Subscriber},
+    serial::read_packages,
+    source::{forward_commands, CommandSink, LinkStats, LinkStatus, TelemetrySource},
+    Message,
+};
+
+/// A TCP connection to e.g. a software-in-the-loop simulator, which
+/// speaks the same protocol as a serial device.
+///
+/// Clones share the same connection, so that commands are sent over
+/// the connection telemetry is received on.
+#[derive(Clone, Debug)]
+pub struct TcpLink {
+    address: String,
+    writer: Arc<Mutex<Option<OwnedWriteHalf>>>,
+}
+
+impl TcpLink {
+    pub fn new(address: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            writer: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+#[async_trait]
+impl TelemetrySource for TcpLink {
+    async fn listen(&mut self, message_bus: Bus) -> Result<()> {
+        let mut stats = LinkStats::default();
+        let mut retries = 0;
+        loop {
+            match TcpStream::connect(&self.address).await {
+                Ok(stream) => {
+                    let (mut reader, writer) = stream.into_split();
+                    *self.writer.lock().await = Some(writer);
+                    retries = 0;
+                    message_bus.send(Message::Link(LinkStatus::Connected {
+                        link: self.address.clone(),
+                    }));
+
+                    read_packages(&mut reader, None, &mut stats, &message_bus).await;
+
+                    *self.writer.lock().await = None;
+                    message_bus.send(Message::Link(LinkStatus::Disconnected {
+                        link: self.address.clone(),
+                        reason: "connection closed".to_string(),
+                        retries,
+                    }));
+                    // Don't hammer a simulator which keeps closing the
+                    // connection right away.
+                    sleep(Duration::from_secs(1)).await;
+                }
+                Err(e) => {
+                    retries += 1;
+                    message_bus.send(Message::Link(LinkStatus::Disconnected {
+                        link: self.address.clone(),
+                        reason: e.to_string(),
+                        retries,
+                    }));
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CommandSink for TcpLink {
+    async fn send_commands(&mut self, rx: Subscriber, tx: Bus) -> Result<()> {
+        forward_commands(&self.writer, rx, tx).await
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use postcard::take_from_bytes_cobs;
+use postcard_telemetry::transport;
+use tokio::{
+    net::UdpSocket,
+    select,
+    sync::OnceCell,
+    time::{interval, sleep},
+};
+
+use crate::{
+    bus::{Bus, Subscriber},
+    log::{Log, Origin},
+    serial::publish_package,
+    source::{CommandSent, CommandSink, LinkStats, LinkStatus, TelemetrySource, STATS_INTERVAL},
+    Command, Message,
+};
+
+/// Largest possible UDP payload.
+const MAX_DATAGRAM_SIZE: usize = 65_535;
+
+/// A UDP socket, e.g. bridged to a radio modem, which carries one
+/// COBS-encoded package per datagram.
+///
+/// Clones share the same socket. Commands are sent to the configured
+/// peer if there is one, otherwise to whoever last sent us a
+/// datagram.
+#[derive(Clone, Debug)]
+pub struct UdpLink {
+    bind: String,
+    peer: Option<String>,
+    last_peer: Arc<Mutex<Option<SocketAddr>>>,
+    socket: Arc<OnceCell<UdpSocket>>,
+}
+
+impl UdpLink {
+    pub fn new(bind: &str, peer: Option<&str>) -> Self {
+        Self {
+            bind: bind.to_string(),
+            peer: peer.map(|p| p.to_string()),
+            last_peer: Arc::new(Mutex::new(None)),
+            socket: Arc::new(OnceCell::new()),
+        }
+    }
+
+    /// Returns the shared socket, binding it on first use.
+    async fn socket(&self) -> Result<&UdpSocket> {
+        self.socket
+            .get_or_try_init(|| UdpSocket::bind(&self.bind))
+            .await
+            .with_context(|| format!("Failed to bind UDP socket to {}", self.bind))
+    }
+
+    async fn send(&self, cmd: &str) -> Result<()> {
+        let socket = self.socket().await?;
+        let last_peer = *self.last_peer.lock().unwrap();
+        match (&self.peer, last_peer) {
+            (Some(peer), _) => socket.send_to(cmd.as_bytes(), peer).await?,
+            (None, Some(peer)) => socket.send_to(cmd.as_bytes(), peer).await?,
+            (None, None) => return Err(anyhow!("no peer to send to yet")),
+        };
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TelemetrySource for UdpLink {
+    async fn listen(&mut self, message_bus: Bus) -> Result<()> {
+        let link = format!("udp://{}", self.bind);
+        let mut retries = 0;
+        let socket = loop {
+            match self.socket().await {
+                Ok(socket) => break socket,
+                Err(e) => {
+                    // E.g. the port is still in use, which may not last.
+                    retries += 1;
+                    message_bus.send(Message::Link(LinkStatus::Disconnected {
+                        link: link.clone(),
+                        reason: format!("{e:#}"),
+                        retries,
+                    }));
+                    sleep(Duration::from_secs(1)).await;
+                }
+            }
+        };
+        message_bus.send(Message::Link(LinkStatus::Connected { link }));
+
+        let mut buf = vec![0u8; MAX_DATAGRAM_SIZE];
+        let mut stats = LinkStats::default();
+        let mut stats_interval = interval(STATS_INTERVAL);
+        loop {
+            select! {
+                result = socket.recv_from(&mut buf) => match result {
+                    Ok((n, peer)) => {
+                        *self.last_peer.lock().unwrap() = Some(peer);
+                        stats.bytes += n as u64;
+                        let raw_package = buf[..n].to_vec();
+                        match take_from_bytes_cobs::<transport::Package>(&mut buf[..n]) {
+                            Ok((package, _)) => {
+                                stats.packets += 1;
+                                publish_package(package, &message_bus);
+                            }
+                            Err(e) => {
+                                match e {
+                                    postcard::Error::DeserializeBadEnum => {
+                                        stats.unknown_packages += 1
+                                    }
+                                    _ => stats.bad_encodings += 1,
+                                }
+                                stats.reject(&raw_package);
+                                message_bus.send(Message::Log(Log::warning(
+                                    Origin::Link,
+                                    format!("Got bad datagram from {peer}: {e}"),
+                                )));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        message_bus.send(Message::Log(Log::warning(
+                            Origin::Link,
+                            format!("Failed to receive datagram: {e}"),
+                        )));
+                    }
+                },
+                _ = stats_interval.tick() => {
+                    message_bus.send(Message::LinkStats(stats.clone()));
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CommandSink for UdpLink {
+    async fn send_commands(&mut self, mut rx: Subscriber, tx: Bus) -> Result<()> {
+        while let Some(msg) = rx.recv().await {
+            if let Message::Command(Command::SendCommand(cmd)) = msg {
+                let result = self.send(&cmd).await.map_err(|e| e.to_string());
+                tx.send(Message::CommandSent(CommandSent::new(&cmd, result)));
+            }
+        }
+        Ok(())
+    }
+}